@@ -123,8 +123,8 @@ impl BatchPaymentContract {
             if item.amount < min_amount || item.amount > max_amount {
                 panic!("payment amount outside configured limits");
             }
-            if item.memo.len() == 0 {
-                panic!("payment at index {}: memo must not be empty");
+            if item.memo.is_empty() {
+                panic!("payment at index {}: memo must not be empty", i);
             }
         }
 
@@ -139,7 +139,7 @@ impl BatchPaymentContract {
             let seed: u64 = (env.ledger().sequence() as u64) * 1000 + i as u64;
             let id_bytes: BytesN<32> = env.crypto().sha256(
                 &soroban_sdk::Bytes::from_slice(&env, &seed.to_be_bytes()),
-            );
+            ).into();
 
             // Emit PaymentCreated event — one per batch entry.
             env.events().publish(
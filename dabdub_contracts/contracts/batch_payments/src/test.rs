@@ -11,8 +11,8 @@ fn make_payment(env: &Env, amount: i128, memo: &str) -> PaymentInput {
     }
 }
 
-fn deploy_and_init(env: &Env, min_amount: i128, max_amount: i128) -> (BatchPaymentContractClient, Address) {
-    let contract_id = env.register_contract(None, BatchPaymentContract);
+fn deploy_and_init(env: &Env, min_amount: i128, max_amount: i128) -> (BatchPaymentContractClient<'_>, Address) {
+    let contract_id = env.register(BatchPaymentContract, ());
     let client = BatchPaymentContractClient::new(env, &contract_id);
     let admin = Address::generate(env);
     client.init(&admin, &min_amount, &max_amount);
@@ -147,7 +147,7 @@ fn test_payment_created_events_emitted() {
 #[test]
 fn test_max_batch_size_is_20() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, BatchPaymentContract);
+    let contract_id = env.register(BatchPaymentContract, ());
     let client = BatchPaymentContractClient::new(&env, &contract_id);
     assert_eq!(client.max_batch_size(), 20);
 }
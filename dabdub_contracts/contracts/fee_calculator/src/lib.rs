@@ -122,7 +122,7 @@ impl FeeCalculatorContract {
     }
 
     fn validate_tiers(tiers: &Vec<FeeTier>) {
-        if tiers.len() == 0 {
+        if tiers.is_empty() {
             panic!("tiers must not be empty");
         }
 
@@ -35,7 +35,7 @@ impl FeeDistributorContract {
         lp_share_bps: i128,
         usdc_token: Address,
     ) {
-        assert!(lp_share_bps >= 0 && lp_share_bps <= BPS_DENOM, "bps out of range");
+        assert!((0..=BPS_DENOM).contains(&lp_share_bps), "bps out of range");
         env.storage().instance().set(&DataKey::Admin, &admin);
         env.storage().instance().set(&DataKey::Treasury, &treasury);
         env.storage().instance().set(&DataKey::LpAddress, &lp_address);
@@ -88,7 +88,7 @@ impl FeeDistributorContract {
     pub fn set_lp_share(env: Env, caller: Address, bps: i128) {
         caller.require_auth();
         Self::require_admin(&env, &caller);
-        assert!(bps >= 0 && bps <= BPS_DENOM, "bps out of range");
+        assert!((0..=BPS_DENOM).contains(&bps), "bps out of range");
         env.storage().instance().set(&DataKey::LpShareBps, &bps);
     }
 
@@ -23,8 +23,14 @@ pub struct MerchantRecord {
     pub name: String,
     pub status: MerchantStatus,
     pub kyc_verified: bool,
+    pub fee_bps: u32,
 }
 
+/// Fee tier assigned to newly registered merchants (1.5 %).
+const DEFAULT_FEE_BPS: u32 = 150;
+/// Upper bound for a merchant fee tier (10 %).
+const MAX_FEE_BPS: u32 = 1000;
+
 /// Storage keys used by the registry.
 #[contracttype]
 #[derive(Clone)]
@@ -59,6 +65,12 @@ struct KYCStatusUpdatedEvent {
     verified: bool,
 }
 
+#[contracttype]
+struct FeeTierUpdatedEvent {
+    merchant: Address,
+    fee_bps: u32,
+}
+
 #[contracttype]
 struct AdminTransferredEvent {
     old_admin: Address,
@@ -101,6 +113,7 @@ impl MerchantRegistryContract {
             name: name.clone(),
             status: MerchantStatus::Active,
             kyc_verified: false,
+            fee_bps: DEFAULT_FEE_BPS,
         };
         env.storage().persistent().set(&key, &record);
 
@@ -189,6 +202,31 @@ impl MerchantRegistryContract {
         );
     }
 
+    /// Set the fee tier (in basis points) for a merchant.  Admin-only.
+    pub fn update_fee_tier(env: Env, caller: Address, merchant: Address, fee_bps: u32) {
+        caller.require_auth();
+        Self::require_admin(&env, &caller);
+
+        if fee_bps > MAX_FEE_BPS {
+            panic!("fee_bps exceeds maximum of 1000");
+        }
+
+        let key = DataKey::Merchant(merchant.clone());
+        let mut record: MerchantRecord = env
+            .storage()
+            .persistent()
+            .get(&key)
+            .expect("Merchant not found");
+
+        record.fee_bps = fee_bps;
+        env.storage().persistent().set(&key, &record);
+
+        env.events().publish(
+            ("REGISTRY", "fee_tier_updated"),
+            FeeTierUpdatedEvent { merchant: merchant.clone(), fee_bps },
+        );
+    }
+
     // ------------------------------------------------------------------
     // Queries
     // ------------------------------------------------------------------
@@ -221,6 +259,11 @@ impl MerchantRegistryContract {
         record.kyc_verified
     }
 
+    /// Returns the merchant's fee tier in basis points.
+    pub fn get_fee_tier(env: Env, merchant: Address) -> u32 {
+        Self::get_merchant(env, merchant).fee_bps
+    }
+
     pub fn get_admin(env: Env) -> Address {
         env.storage().instance().get(&DataKey::Admin).unwrap()
     }
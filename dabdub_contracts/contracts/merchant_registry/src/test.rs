@@ -274,7 +274,7 @@ fn test_get_merchant_includes_kyc_field() {
     client.register_merchant(&admin, &merchant, &sample_name(&env));
     let record = client.get_merchant(&merchant);
 
-    assert_eq!(record.kyc_verified, false);
+    assert!(!record.kyc_verified);
 }
 
 // ---------------------------------------------------------------------------
@@ -0,0 +1,120 @@
+use soroban_sdk::contracterror;
+
+/// Every failure `PaymentEscrowContract` can return.
+///
+/// Codes are stable and grouped by area; new variants take the next free code
+/// in their group and existing codes are never reused. The contract spec caps
+/// an error enum at 50 cases, so closely related failures share a code.
+/// Codes 3, 4, 5, 21, 37, 59, 70, 91, 92, 111, 115, 150 and 154 were folded
+/// into their neighbours and stay retired.
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum EscrowError {
+    // Authorization
+    /// Caller is not the admin, or not the pending admin accepting a transfer.
+    NotAdmin = 1,
+    /// Caller does not hold the upgrader, operator or config-admin role
+    /// required, or is not an arbiter or emergency signer.
+    MissingRole = 2,
+    /// Caller (or named dispute winner) is not the payment's customer or merchant.
+    NotPaymentParty = 6,
+
+    // Configuration
+    /// Default TTL, emergency cooldown/threshold, token limits or dispute
+    /// timeout; a duplicate arbiter or signer; or no payment request contract
+    /// is set for `deposit_invoice`.
+    InvalidConfig = 20,
+    AlreadyPaused = 22,
+    NotPaused = 23,
+    /// Arbiter, signer, split, milestone, evidence or batch cap reached.
+    LimitExceeded = 24,
+
+    // Deposits
+    /// Amount is not positive, exceeds what is left in the escrow, or is a
+    /// partial refund of a milestone escrow.
+    InvalidAmount = 30,
+    /// `ttl_ledgers`, `auto_release_after`, a stream's ledger range or a
+    /// subscription's period or end ledger is out of range.
+    InvalidTtl = 31,
+    /// The payment id is taken, or is reserved for subscription charges.
+    PaymentAlreadyExists = 32,
+    MerchantNotActive = 33,
+    TokenNotAllowed = 34,
+    AmountOutOfRange = 35,
+    DepositsPaused = 36,
+
+    // Payment state
+    PaymentNotFound = 50,
+    PaymentNotPending = 51,
+    PaymentFullyReleased = 52,
+    PaymentExpired = 53,
+    PaymentNotExpired = 54,
+    /// An asset is drained: escrows holding it and all new deposits are frozen
+    /// until it is restored.
+    PaymentFrozen = 55,
+    DisputeOpen = 56,
+    MerchantNotKycVerified = 57,
+    ReleasesPaused = 58,
+    /// No auto-release is set, or its ledger has not passed yet.
+    AutoReleaseNotDue = 60,
+    /// The call does not apply to this kind of escrow: a stream, preimage or
+    /// attestation call on an escrow that is not one, a lump-sum release of a
+    /// streamed or hashlocked escrow, or a dispute of a hashlocked one.
+    UnsupportedEscrowKind = 61,
+
+    // Disputes
+    DisputeNotOpen = 71,
+    DisputeWindowExpired = 72,
+    /// Resolution amounts do not add up.
+    ResolutionMismatch = 73,
+    DisputeDeadlineNotReached = 74,
+
+    // Split payouts
+    /// Shares are empty, a recipient repeats, or the shares do not add up to
+    /// the escrow amount.
+    InvalidSplit = 90,
+
+    // Milestones
+    /// The milestone schedule is invalid, missing, or has no such index.
+    InvalidMilestones = 110,
+    MilestoneAlreadySettled = 112,
+    MilestoneDeadlineNotReached = 113,
+    ReleasedByMilestone = 114,
+
+    // Emergency controls
+    EmergencyQuorumNotMet = 151,
+    EmergencyCooldownActive = 152,
+    /// No selected asset has a balance, or one is already drained.
+    NothingToDrain = 153,
+    AssetNotDrained = 155,
+
+    // Invoices
+    /// The payment request contract has no such request.
+    InvoiceNotFound = 170,
+    /// The request is no longer pending, or is past its expiry.
+    InvoiceNotPayable = 171,
+    /// Merchant, asset or amount differs from the request's.
+    InvoiceMismatch = 172,
+
+    // Subscriptions
+    SubscriptionNotFound = 180,
+    SubscriptionAlreadyExists = 181,
+    /// The subscription was cancelled or has charged its last period.
+    SubscriptionNotActive = 182,
+    /// The subscription's next period has not started yet.
+    SubscriptionNotDue = 183,
+
+    // Streams
+    /// Nothing has vested since the last withdrawal, or nothing unvested is
+    /// left to cancel.
+    StreamBalanceEmpty = 190,
+
+    // Hashlocks
+    /// The preimage does not hash to the escrow's hashlock.
+    PreimageMismatch = 200,
+
+    // Attestations
+    /// The attested condition hash is not the one the escrow was locked to.
+    ConditionMismatch = 210,
+}
@@ -1,10 +1,16 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 
+mod error;
 mod test;
 
+pub use error::EscrowError;
+
 use soroban_sdk::{
-    contract, contractclient, contractimpl, contracttype, token, Address, BytesN, Env, String,
-    Vec,
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    contract, contractclient, contractimpl, contracttype, symbol_short, token, vec,
+    xdr::ToXdr,
+    Address, Bytes, BytesN, Env, IntoVal, Map, String, Symbol, TryFromVal, Val, Vec,
 };
 
 #[contracttype]
@@ -12,13 +18,100 @@ use soroban_sdk::{
 pub enum AssetType {
     Xlm,
     Usdc,
+    /// Any SEP-41 token contract on the admin-managed allowlist.
+    Token(Address),
+}
+
+/// Deposit bounds for an allowlisted token, in the token's base units.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenLimits {
+    pub min_amount: i128,
+    pub max_amount: i128,
 }
 
 /// Thin client interface for the MerchantRegistry contract.
 #[contractclient(name = "MerchantRegistryClient")]
 #[allow(dead_code)]
 trait MerchantRegistry {
-    fn is_approved(env: Env, merchant: Address) -> bool;
+    fn is_merchant_active(env: Env, merchant: Address) -> bool;
+    fn is_kyc_verified(env: Env, merchant: Address) -> bool;
+}
+
+/// Thin client interface for the FeeCalculator contract.
+#[contractclient(name = "FeeCalculatorClient")]
+#[allow(dead_code)]
+trait FeeCalculator {
+    fn calculate_fee(env: Env, merchant: Address, amount: i128) -> (i128, i128, u32);
+}
+
+/// Thin client interface for the FeeDistributor contract.
+#[contractclient(name = "FeeDistributorClient")]
+#[allow(dead_code)]
+trait FeeDistributor {
+    fn distribute(env: Env, caller: Address, total_fee: i128);
+}
+
+/// Thin client interface for the SettlementLedger contract.
+#[contractclient(name = "SettlementLedgerClient")]
+#[allow(dead_code)]
+trait SettlementLedger {
+    fn record_settlement(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        fee: i128,
+        net: i128,
+        timestamp: u64,
+        fiat_ref: String,
+    );
+}
+
+/// Status of a payment request, as stored by the PaymentRequest contract.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InvoiceStatus {
+    Pending = 0,
+    Confirmed = 1,
+    Settling = 2,
+    Settled = 3,
+    Failed = 4,
+    Expired = 5,
+}
+
+/// A payment request, as returned by the PaymentRequest contract.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+    pub id: String,
+    pub merchant: Address,
+    pub amount: i128,
+    pub asset: Address,
+    pub status: InvoiceStatus,
+    pub created_at: u64,
+    /// Unix timestamp after which the request can no longer be paid.
+    pub expiry: u64,
+}
+
+/// Thin client interface for the PaymentRequest contract.
+#[contractclient(name = "PaymentRequestClient")]
+#[allow(dead_code)]
+trait PaymentRequest {
+    fn get_payment(env: Env, id: String) -> Invoice;
+    fn confirm(env: Env, caller: Address, id: String);
+    fn fail(env: Env, id: String);
+}
+
+/// Contracts that take a fee out of USDC merchant payouts.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeHooks {
+    /// Quotes the fee for a merchant and payout amount.
+    pub calculator: Address,
+    /// Pulls the fee from the escrow and splits it between treasury and LPs.
+    pub distributor: Address,
 }
 
 #[contracttype]
@@ -28,6 +121,11 @@ pub enum PaymentStatus {
     Disputed,
     Released,
     Expired,
+    /// Dispute settled with the balance divided between merchant and customer.
+    Resolved,
+    /// The escrow's asset was moved to the treasury by `emergency_drain`.
+    /// Reported for open payments until the asset is restored; never stored.
+    Frozen,
 }
 
 #[contracttype]
@@ -43,6 +141,231 @@ pub struct PaymentEscrow {
     pub dispute_window_end: u32,
     pub dispute_reason: Option<String>,
     pub asset_type: AssetType,
+    pub token: Address,
+    /// Where refunds go when they should not go to `customer`.
+    pub refund_to: Option<Address>,
+    /// Ledger after which anyone may `claim` the escrow for the merchant.
+    pub auto_release_after: Option<u32>,
+    /// Payment request (invoice) this escrow pays, if any.
+    pub invoice_id: Option<String>,
+    /// Ledger from which a streamed escrow starts vesting to the merchant.
+    pub stream_start: Option<u32>,
+    /// Ledger by which a streamed escrow has fully vested.
+    pub stream_end: Option<u32>,
+    /// SHA-256 digest whose preimage releases a hashlocked escrow.
+    pub hashlock: Option<BytesN<32>>,
+    /// Ed25519 public key whose attestation releases the escrow.
+    pub attester: Option<BytesN<32>>,
+    /// Condition the attester must attest to, e.g. a delivery receipt hash.
+    pub condition_hash: Option<BytesN<32>>,
+}
+
+/// How a split recipient's portion of a deposit is defined.
+///
+/// Fixed amounts are carved out of the deposit first; basis-point shares then
+/// divide whatever is left and must add up to 10 000.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplitShare {
+    Bps(u32),
+    Fixed(i128),
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplitRecipientInput {
+    pub recipient: Address,
+    pub share: SplitShare,
+}
+
+/// A split recipient with its share resolved to an absolute amount.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplitRecipient {
+    pub recipient: Address,
+    pub amount: i128,
+    pub released: i128,
+}
+
+/// What happens to a milestone nobody approves before its deadline.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MilestoneDefault {
+    Release,
+    Refund,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MilestoneStatus {
+    Pending,
+    Released,
+    Refunded,
+    /// Closed by a dispute resolution that divided the balance.
+    Resolved,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MilestoneInput {
+    pub label: String,
+    pub amount: i128,
+    pub deadline: u32,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Milestone {
+    pub label: String,
+    pub amount: i128,
+    pub deadline: u32,
+    pub status: MilestoneStatus,
+}
+
+/// One piece of dispute evidence: a hash of an off-chain file plus who
+/// submitted it and when.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvidenceEntry {
+    pub hash: BytesN<32>,
+    pub submitter: Address,
+    pub timestamp: u64,
+}
+
+/// Single-holder roles split out of the admin key.  A role with no holder
+/// falls back to the admin.  Dispute resolution uses the arbiter set instead.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    /// May upgrade the contract WASM.
+    Upgrader,
+    /// May release escrowed funds to merchants.
+    Operator,
+    /// May change the registry, token allowlist and dispute timeout.
+    ConfigAdmin,
+}
+
+/// What a pause flag applies to.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PauseScope {
+    /// All new deposits.
+    Deposits,
+    /// New deposits to a single merchant.
+    Merchant(Address),
+    /// Every payout to merchants.  Refunds to customers stay open.
+    Releases,
+}
+
+/// Balance moved to the treasury for one asset by `emergency_drain`.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrainRecord {
+    pub token: Address,
+    pub amount: i128,
+    pub ledger: u32,
+}
+
+/// Outcome of one payment in a batch call.  `amount` is what was paid out;
+/// `error` is the `EscrowError` code explaining why the payment was skipped.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchItemResult {
+    pub payment_id: BytesN<32>,
+    pub amount: i128,
+    pub error: Option<u32>,
+}
+
+/// An append-only list of escrows, stored in pages of `INDEX_PAGE_SIZE` ids.
+///
+/// Indexes hold ids only; queries read each payment's current status, so
+/// they stay correct through every status transition without being
+/// rewritten.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EscrowIndex {
+    Merchant(Address),
+    Customer(Address),
+    /// Escrows whose expiry ledger divided by `EXPIRY_BUCKET_LEDGERS` equals
+    /// the bucket.
+    Expiry(u32),
+}
+
+/// One page of an index query.  `next` is the `start` to pass for the
+/// following page, or `None` once the index has been read to the end.
+#[contracttype]
+#[derive(Clone, Debug)]
+pub struct EscrowPage {
+    pub payments: Vec<PaymentEscrow>,
+    pub next: Option<u32>,
+}
+
+/// Milestone schedule fixed at deposit time.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MilestonePlan {
+    pub milestones: Vec<Milestone>,
+    pub on_deadline: MilestoneDefault,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,
+    /// No new periods start; those due before the cancel can still be charged.
+    Cancelled,
+    /// Every period before `end_ledger` has been charged.
+    Ended,
+}
+
+/// A customer's standing authorization to pay a merchant every
+/// `period_ledgers`, charged into one escrow per period.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    pub subscription_id: BytesN<32>,
+    pub customer: Address,
+    pub merchant: Address,
+    pub asset_type: AssetType,
+    pub token: Address,
+    /// Amount escrowed each period; the most a single charge can take.
+    pub amount_per_period: i128,
+    pub period_ledgers: u32,
+    /// Periods falling due at or after this ledger are not charged.  Pulled
+    /// in by `cancel_subscription`.
+    pub end_ledger: u32,
+    /// TTL given to each period's escrow.
+    pub ttl_ledgers: u32,
+    /// Ledger from which the next period can be charged.
+    pub next_charge_ledger: u32,
+    pub periods_charged: u32,
+    pub status: SubscriptionStatus,
+}
+
+/// Where the funds for a new escrow come from.
+enum Funding {
+    /// Transferred from an address that authorizes the deposit.
+    Transfer(Address),
+    /// Pulled from the customer's allowance to this contract.
+    Allowance,
+}
+
+/// Record layout written before `token` was added to `PaymentEscrow`.
+/// Escrows created by earlier WASM versions are still stored in this shape
+/// and are upgraded on read.
+#[contracttype]
+#[derive(Clone, Debug)]
+struct LegacyPaymentEscrow {
+    payment_id: BytesN<32>,
+    amount: i128,
+    released_amount: i128,
+    merchant: Address,
+    customer: Address,
+    status: PaymentStatus,
+    expiry: u32,
+    dispute_window_end: u32,
+    dispute_reason: Option<String>,
+    asset_type: AssetType,
 }
 
 #[contracttype]
@@ -54,37 +377,285 @@ pub enum DataKey {
     DefaultTtlLedgers,
     RegistryContract,
     EmergencySigners,
+    EmergencyThreshold,
     EmergencyTreasury,
     EmergencyCooldownLedgers,
     EmergencyLastDrainLedger,
     Payment(BytesN<32>),
     Version,
+    AllowedToken(Address),
+    Split(BytesN<32>),
+    Milestones(BytesN<32>),
+    Arbiters,
+    Evidence(BytesN<32>),
+    DisputeTimeoutLedgers,
+    DisputeDeadline(BytesN<32>),
+    DrainedAssets,
+    Paused(PauseScope),
+    PendingAdmin,
+    Role(Role),
+    IndexLen(EscrowIndex),
+    IndexPage(EscrowIndex, u32),
+    FeeHooks,
+    SettlementLedger,
+    InvoiceContract,
+    Subscription(BytesN<32>),
 }
 
+/// Version of the `EscrowEvent`, `DisputeResolvedEvent` and
+/// `DisputeTimeoutEvent` payloads.  Bumped whenever their fields change;
+/// version 1 was the bare `payment_id`/`amount` payload.
+pub const EVENT_SCHEMA_VERSION: u32 = 2;
+
+/// Payload of every payment transition, published under `("ESCROW", topic)`
+/// with the payment as it stands after the transition.
 #[contracttype]
-struct DepositEvent {
-    payment_id: BytesN<32>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct EscrowEvent {
+    pub version: u32,
+    pub payment_id: BytesN<32>,
+    /// Token contract of the escrowed asset.
+    pub asset: Address,
+    pub customer: Address,
+    pub merchant: Address,
+    /// Who was paid `amount`; `None` when no funds left the escrow.
+    pub recipient: Option<Address>,
+    pub amount: i128,
+    /// Same as `status` for the deposit that created the payment.
+    pub prev_status: PaymentStatus,
+    pub status: PaymentStatus,
+    /// Cumulative amount paid out of the escrow, to either party.
+    pub released_amount: i128,
+    pub expiry: u32,
+    pub dispute_reason: Option<String>,
+    /// Index of the milestone settled, for milestone transitions.
+    pub milestone: Option<u32>,
+}
+
+/// Payload of `("ESCROW", "dispute_resolved")`: one event for the whole
+/// resolution, carrying both parties' shares.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisputeResolvedEvent {
+    pub version: u32,
+    pub payment_id: BytesN<32>,
+    pub asset: Address,
+    pub customer: Address,
+    pub merchant: Address,
+    /// Where `customer_amount` was sent.
+    pub refund_to: Address,
+    pub merchant_amount: i128,
+    pub customer_amount: i128,
+    pub prev_status: PaymentStatus,
+    pub status: PaymentStatus,
+    pub released_amount: i128,
+    pub dispute_reason: Option<String>,
+}
+
+/// Payload of `("ESCROW", "dispute_timeout")`.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisputeTimeoutEvent {
+    pub version: u32,
+    pub payment_id: BytesN<32>,
+    pub asset: Address,
+    pub customer: Address,
+    pub merchant: Address,
+    pub recipient: Address,
+    pub amount: i128,
+    /// Resolution deadline the dispute passed.
+    pub deadline: u32,
+    pub prev_status: PaymentStatus,
+    pub status: PaymentStatus,
+    pub released_amount: i128,
+    pub dispute_reason: Option<String>,
+}
+
+#[contracttype]
+struct SubscriptionEvent {
+    subscription_id: BytesN<32>,
     customer: Address,
     merchant: Address,
+    amount_per_period: i128,
+    period_ledgers: u32,
+    end_ledger: u32,
+}
+
+#[contracttype]
+struct SubscriptionChargeEvent {
+    subscription_id: BytesN<32>,
+    payment_id: BytesN<32>,
+    period: u32,
     amount: i128,
-    expiry: u32,
 }
 
 #[contracttype]
-struct EscrowStateEvent {
+struct SubscriptionCancelEvent {
+    subscription_id: BytesN<32>,
+    periods_charged: u32,
+    /// Allowance withdrawn for the periods that will no longer be charged.
+    released_allowance: i128,
+}
+
+/// A hashlock claim whose settlement ledger write failed; the claim itself
+/// went through.
+#[contracttype]
+struct SettlementSkippedEvent {
+    payment_id: BytesN<32>,
+    settlement_id: BytesN<32>,
+    amount: i128,
+}
+
+#[contracttype]
+struct PreimageEvent {
+    payment_id: BytesN<32>,
+    hashlock: BytesN<32>,
+    preimage: Bytes,
+}
+
+/// What an attester signs to release an escrow.  Binding the network,
+/// contract and payment keeps a signature from being replayed elsewhere; the
+/// escrow can only be released once, so it cannot be replayed here either.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttestationPayload {
+    pub network_id: BytesN<32>,
+    pub contract: Address,
+    pub payment_id: BytesN<32>,
+    pub condition_hash: BytesN<32>,
+}
+
+#[contracttype]
+struct AttestationEvent {
+    payment_id: BytesN<32>,
+    attester: BytesN<32>,
+    condition_hash: BytesN<32>,
+}
+
+#[contracttype]
+struct FeeChargedEvent {
     payment_id: BytesN<32>,
     amount: i128,
+    fee: i128,
+    fee_bps: u32,
+}
+
+#[contracttype]
+struct SplitPayoutEvent {
+    payment_id: BytesN<32>,
+    recipient: Address,
+    amount: i128,
+}
+
+#[contracttype]
+struct TokenAllowedEvent {
+    token: Address,
+    min_amount: i128,
+    max_amount: i128,
+}
+
+#[contracttype]
+struct TokenRemovedEvent {
+    token: Address,
+}
+
+#[contracttype]
+struct ArbiterEvent {
+    arbiter: Address,
+}
+
+#[contracttype]
+struct EvidenceEvent {
+    payment_id: BytesN<32>,
+    submitter: Address,
+    hash: BytesN<32>,
 }
 
 #[contracttype]
 struct EmergencyDrainEvent {
+    token: Address,
     amount: i128,
     caller: Address,
 }
 
+#[contracttype]
+struct RoleEvent {
+    role: Role,
+    account: Option<Address>,
+}
+
+#[contracttype]
+struct AdminEvent {
+    previous: Address,
+    next: Address,
+}
+
+#[contracttype]
+struct EmergencySignersEvent {
+    signers: Vec<Address>,
+    threshold: u32,
+}
+
+#[contracttype]
+struct PauseEvent {
+    scope: PauseScope,
+    caller: Address,
+}
+
+#[contracttype]
+struct AssetRestoredEvent {
+    token: Address,
+    amount: i128,
+}
+
 const MAX_DISPUTE_WINDOW_LEDGERS: u32 = 51_840;
 const MAX_TTL_LEDGERS: u32 = 518_400;
-const EMERGENCY_SIGNER_COUNT: u32 = 3;
+const MAX_EMERGENCY_SIGNERS: u32 = 10;
+const MIN_EMERGENCY_THRESHOLD: u32 = 2;
+const MAX_SPLIT_RECIPIENTS: u32 = 10;
+const BPS_DENOMINATOR: i128 = 10_000;
+const MAX_MILESTONES: u32 = 20;
+const MAX_ARBITERS: u32 = 10;
+/// Largest batch that stays inside the 40-entry transaction footprint when
+/// every payment goes to a different merchant (about four entries per item).
+const MAX_BATCH_SIZE: u32 = 8;
+/// Batch cap while a merchant registry is set: the KYC check reads one more
+/// entry per item, plus the registry's instance and code.
+const MAX_KYC_BATCH_SIZE: u32 = 6;
+/// Batch cap while fee hooks are set, with or without a settlement ledger
+/// or registry: each payout also writes the merchant's fee volume and emits
+/// the calculator, distributor and token events, which makes the event size
+/// budget the binding limit.
+const MAX_FEE_BATCH_SIZE: u32 = 3;
+/// Batch cap while a settlement ledger is set without fee hooks: each release
+/// also writes a ledger record, the merchant's index page and its settlement
+/// count.  The registry's KYC read takes it down by one more.
+const MAX_SETTLED_BATCH_SIZE: u32 = 4;
+const MAX_KYC_SETTLED_BATCH_SIZE: u32 = 3;
+/// Batch cap with fee hooks, a settlement ledger and a registry all set,
+/// where the footprint binds again (nine entries per item).
+const MAX_FULL_BATCH_SIZE: u32 = 2;
+/// Instance storage (which also holds the token allowlist and pause flags)
+/// and contract code are kept alive for about a month and topped up once less
+/// than a week remains.
+const CONFIG_TTL_THRESHOLD: u32 = 120_960;
+const CONFIG_TTL_EXTEND_TO: u32 = 535_680;
+/// Payment entries outlive the escrow's last deadline by about a week, so a
+/// late refund or dispute timeout still finds them.
+const PAYMENT_TTL_GRACE_LEDGERS: u32 = 120_960;
+/// Leading bytes of every subscription charge's payment id.
+const SUBSCRIPTION_ID_TAG: [u8; 4] = *b"SUB:";
+/// Roughly seven days at five seconds per ledger.
+const DEFAULT_DISPUTE_TIMEOUT_LEDGERS: u32 = 120_960;
+/// Each party's share of a dispute's evidence log, so neither can fill it
+/// and shut the other out.
+const MAX_EVIDENCE_PER_PARTY: u32 = 25;
+const INDEX_PAGE_SIZE: u32 = 64;
+/// Index queries read every payment they return; this keeps a query inside
+/// the read-entry limit.
+const MAX_INDEX_QUERY_SIZE: u32 = 20;
+/// Roughly one day at five seconds per ledger.
+const EXPIRY_BUCKET_LEDGERS: u32 = 17_280;
 
 #[contract]
 pub struct PaymentEscrowContract;
@@ -99,24 +670,14 @@ impl PaymentEscrowContract {
         default_ttl_ledgers: u32,
         registry: Option<Address>,
         emergency_signers: Vec<Address>,
+        emergency_threshold: u32,
         emergency_treasury: Address,
         emergency_cooldown_ledgers: u32,
-    ) {
-        if default_ttl_ledgers == 0 {
-            panic!("Default TTL must be > 0");
-        }
-        if emergency_cooldown_ledgers == 0 {
-            panic!("Emergency cooldown must be > 0");
-        }
-        if emergency_signers.len() != EMERGENCY_SIGNER_COUNT {
-            panic!("Emergency signer set must be exactly 3");
-        }
-        let signer_0 = emergency_signers.get(0).unwrap();
-        let signer_1 = emergency_signers.get(1).unwrap();
-        let signer_2 = emergency_signers.get(2).unwrap();
-        if signer_0 == signer_1 || signer_0 == signer_2 || signer_1 == signer_2 {
-            panic!("Emergency signers must be unique");
+    ) -> Result<(), EscrowError> {
+        if default_ttl_ledgers == 0 || emergency_cooldown_ledgers == 0 {
+            return Err(EscrowError::InvalidConfig);
         }
+        Self::validate_signer_set(&emergency_signers, emergency_threshold)?;
 
         env.storage().instance().set(&DataKey::Admin, &admin);
         env.storage().instance().set(&DataKey::XlmToken, &xlm_token);
@@ -132,6 +693,9 @@ impl PaymentEscrowContract {
         env.storage()
             .instance()
             .set(&DataKey::EmergencySigners, &emergency_signers);
+        env.storage()
+            .instance()
+            .set(&DataKey::EmergencyThreshold, &emergency_threshold);
         env.storage()
             .instance()
             .set(&DataKey::EmergencyTreasury, &emergency_treasury);
@@ -144,469 +708,3246 @@ impl PaymentEscrowContract {
 
         // Initialize contract version to 1.
         env.storage().instance().set(&DataKey::Version, &1u32);
+        Self::extend_instance_ttl(&env);
+        Ok(())
     }
 
-    /// Upgrade the contract WASM. Only callable by the admin.
-    pub fn upgrade(env: Env, caller: Address, new_wasm_hash: BytesN<32>) {
+    /// Upgrade the contract WASM. Only callable by the upgrader.
+    pub fn upgrade(
+        env: Env,
+        caller: Address,
+        new_wasm_hash: BytesN<32>,
+    ) -> Result<(), EscrowError> {
         caller.require_auth();
-        Self::require_admin(&env, &caller);
+        Self::require_role(&env, &caller, Role::Upgrader)?;
 
         let version: u32 = env.storage().instance().get(&DataKey::Version).unwrap_or(0);
         env.storage().instance().set(&DataKey::Version, &(version + 1));
 
         env.deployer().update_current_contract_wasm(new_wasm_hash);
+        Ok(())
     }
 
-    /// Return the current contract version.
-    pub fn get_version(env: Env) -> u32 {
-        env.storage().instance().get(&DataKey::Version).unwrap_or(0)
+    /// Start handing the admin role to `new_admin`.  Admin-only.  Takes
+    /// effect once `new_admin` calls `accept_admin`.
+    pub fn propose_admin(env: Env, caller: Address, new_admin: Address) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_admin(&env, &caller)?;
+
+        env.storage()
+            .instance()
+            .set(&DataKey::PendingAdmin, &new_admin);
+
+        env.events().publish(
+            ("ESCROW", "admin_proposed"),
+            AdminEvent {
+                previous: caller,
+                next: new_admin,
+            },
+        );
+        Ok(())
     }
 
-    /// Update (or remove) the merchant registry address.  Admin-only.
-    pub fn set_registry(env: Env, caller: Address, registry: Option<Address>) {
+    /// Complete an admin handover.  Must be authorized by the proposed admin.
+    pub fn accept_admin(env: Env, caller: Address) -> Result<(), EscrowError> {
         caller.require_auth();
-        Self::require_admin(&env, &caller);
-        match registry {
-            Some(reg) => env
-                .storage()
-                .instance()
-                .set(&DataKey::RegistryContract, &reg),
-            None => env.storage().instance().remove(&DataKey::RegistryContract),
+
+        let pending: Option<Address> = env.storage().instance().get(&DataKey::PendingAdmin);
+        if pending != Some(caller.clone()) {
+            return Err(EscrowError::NotAdmin);
         }
+        let previous: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        env.storage().instance().set(&DataKey::Admin, &caller);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+
+        env.events().publish(
+            ("ESCROW", "admin_transferred"),
+            AdminEvent {
+                previous,
+                next: caller,
+            },
+        );
+        Ok(())
     }
 
-    /// Return the registry contract address if one is configured.
-    pub fn get_registry(env: Env) -> Option<Address> {
-        env.storage().instance().get(&DataKey::RegistryContract)
+    pub fn get_pending_admin(env: Env) -> Result<Option<Address>, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::PendingAdmin))
     }
 
-    pub fn deposit(
+    /// Assign a role to `account`, or clear it so the admin holds it again.
+    /// Admin-only.
+    pub fn set_role(
         env: Env,
-        customer: Address,
-        payment_id: BytesN<32>,
-        merchant: Address,
-        amount: i128,
-        ttl_ledgers: u32,
-        asset_type: AssetType,
-    ) -> BytesN<32> {
-        customer.require_auth();
+        caller: Address,
+        role: Role,
+        account: Option<Address>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_admin(&env, &caller)?;
+
+        let key = DataKey::Role(role.clone());
+        match &account {
+            Some(account) => env.storage().instance().set(&key, account),
+            None => env.storage().instance().remove(&key),
+        }
+
+        env.events()
+            .publish(("ESCROW", "role_changed"), RoleEvent { role, account });
+        Ok(())
+    }
+
+    /// Return the address currently holding `role`.
+    pub fn get_role(env: Env, role: Role) -> Result<Address, EscrowError> {
+        match env.storage().instance().get(&DataKey::Role(role)) {
+            Some(account) => Ok(account),
+            None => Self::get_admin(env),
+        }
+    }
+
+    /// Extend the TTL of a payment and its side entries so they survive until
+    /// after the escrow's last deadline.  Anyone may call this.
+    pub fn bump_payment(env: Env, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id)?;
+        Self::extend_payment_ttl(&env, &payment);
+        Ok(())
+    }
+
+    /// Keeper entry point: extend instance storage and contract code, plus
+    /// every listed payment that still exists.  Anyone may call this.
+    pub fn keep_alive(env: Env, payment_ids: Vec<BytesN<32>>) -> Result<(), EscrowError> {
+        if payment_ids.len() > MAX_BATCH_SIZE {
+            return Err(EscrowError::LimitExceeded);
+        }
+
+        Self::extend_instance_ttl(&env);
+        for payment_id in payment_ids.iter() {
+            if let Some(payment) = Self::load_payment(&env, payment_id) {
+                Self::extend_payment_ttl(&env, &payment);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the current contract version.
+    pub fn get_version(env: Env) -> Result<u32, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::Version).unwrap_or(0))
+    }
+
+    /// Update (or remove) the merchant registry address.  Config admin only.
+    pub fn set_registry(
+        env: Env,
+        caller: Address,
+        registry: Option<Address>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+        match registry {
+            Some(reg) => env
+                .storage()
+                .instance()
+                .set(&DataKey::RegistryContract, &reg),
+            None => env.storage().instance().remove(&DataKey::RegistryContract),
+        }
+        Ok(())
+    }
+
+    /// Return the registry contract address if one is configured.
+    pub fn get_registry(env: Env) -> Result<Option<Address>, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::RegistryContract))
+    }
+
+    /// Set (or remove) the fee calculator and distributor used on USDC
+    /// payouts.  Config admin only.
+    pub fn set_fee_hooks(
+        env: Env,
+        caller: Address,
+        hooks: Option<FeeHooks>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+        match hooks {
+            Some(hooks) => env.storage().instance().set(&DataKey::FeeHooks, &hooks),
+            None => env.storage().instance().remove(&DataKey::FeeHooks),
+        }
+        Ok(())
+    }
+
+    /// Return the fee hooks if they are configured.
+    pub fn get_fee_hooks(env: Env) -> Result<Option<FeeHooks>, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::FeeHooks))
+    }
+
+    /// Update (or remove) the settlement ledger that merchant releases are
+    /// recorded in.  The escrow must be one of the ledger's writers.  Config
+    /// admin only.
+    pub fn set_settlement_ledger(
+        env: Env,
+        caller: Address,
+        ledger: Option<Address>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+        match ledger {
+            Some(ledger) => env
+                .storage()
+                .instance()
+                .set(&DataKey::SettlementLedger, &ledger),
+            None => env.storage().instance().remove(&DataKey::SettlementLedger),
+        }
+        Ok(())
+    }
+
+    /// Return the settlement ledger address if one is configured.
+    pub fn get_settlement_ledger(env: Env) -> Result<Option<Address>, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::SettlementLedger))
+    }
+
+    /// Update (or remove) the payment request contract that `deposit_invoice`
+    /// pays against.  Config admin only.
+    ///
+    /// The escrow must be an authorized confirmer on that contract.
+    pub fn set_invoice_contract(
+        env: Env,
+        caller: Address,
+        contract: Option<Address>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+        match contract {
+            Some(contract) => env
+                .storage()
+                .instance()
+                .set(&DataKey::InvoiceContract, &contract),
+            None => env.storage().instance().remove(&DataKey::InvoiceContract),
+        }
+        Ok(())
+    }
+
+    /// Return the payment request contract address if one is configured.
+    pub fn get_invoice_contract(env: Env) -> Result<Option<Address>, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::InvoiceContract))
+    }
+
+    /// Id under which the release of a payment is recorded in the settlement
+    /// ledger, given how much had been released before it.  The first
+    /// release uses the payment id itself; later ones hash it with
+    /// `released_before`, since ledger records are unique per id.
+    pub fn get_settlement_id(
+        env: Env,
+        payment_id: BytesN<32>,
+        released_before: i128,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Ok(Self::settlement_id(&env, &payment_id, released_before))
+    }
+
+    /// Add a token to the deposit allowlist, or update its limits.  Config
+    /// admin only.
+    ///
+    /// Limits may also be set for the XLM and USDC contracts; those two assets
+    /// are always accepted and are only bounded once an entry exists.
+    pub fn set_token_limits(
+        env: Env,
+        caller: Address,
+        token: Address,
+        min_amount: i128,
+        max_amount: i128,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+
+        if min_amount <= 0 || max_amount < min_amount {
+            return Err(EscrowError::InvalidConfig);
+        }
+
+        let key = DataKey::AllowedToken(token.clone());
+        env.storage().instance().set(
+            &key,
+            &TokenLimits {
+                min_amount,
+                max_amount,
+            },
+        );
+
+        env.events().publish(
+            ("ESCROW", "token_allowed"),
+            TokenAllowedEvent {
+                token,
+                min_amount,
+                max_amount,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a token from the deposit allowlist.  Config admin only.
+    ///
+    /// Escrows already holding the token are unaffected and can still be
+    /// released or refunded.
+    pub fn remove_token(env: Env, caller: Address, token: Address) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+
+        let key = DataKey::AllowedToken(token.clone());
+        if !env.storage().instance().has(&key) {
+            return Err(EscrowError::TokenNotAllowed);
+        }
+        env.storage().instance().remove(&key);
+
+        env.events()
+            .publish(("ESCROW", "token_removed"), TokenRemovedEvent { token });
+        Ok(())
+    }
+
+    /// Return the deposit limits for a token, if it is on the allowlist.
+    pub fn get_token_limits(env: Env, token: Address) -> Result<Option<TokenLimits>, EscrowError> {
+        Ok(env
+            .storage()
+            .instance()
+            .get(&DataKey::AllowedToken(token)))
+    }
+
+    /// Add an address to the arbiter set.  Admin-only.
+    ///
+    /// Once at least one arbiter is configured, only arbiters may resolve
+    /// disputes; until then the admin keeps that power.
+    pub fn add_arbiter(env: Env, caller: Address, arbiter: Address) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_admin(&env, &caller)?;
+
+        let mut arbiters = Self::get_arbiters(env.clone())?;
+        if arbiters.contains(&arbiter) {
+            return Err(EscrowError::InvalidConfig);
+        }
+        if arbiters.len() >= MAX_ARBITERS {
+            return Err(EscrowError::LimitExceeded);
+        }
+        arbiters.push_back(arbiter.clone());
+        env.storage().instance().set(&DataKey::Arbiters, &arbiters);
+
+        env.events()
+            .publish(("ESCROW", "arbiter_added"), ArbiterEvent { arbiter });
+        Ok(())
+    }
+
+    /// Remove an address from the arbiter set.  Admin-only.
+    pub fn remove_arbiter(env: Env, caller: Address, arbiter: Address) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_admin(&env, &caller)?;
+
+        let mut arbiters = Self::get_arbiters(env.clone())?;
+        let index = match arbiters.first_index_of(&arbiter) {
+            Some(index) => index,
+            None => return Err(EscrowError::MissingRole),
+        };
+        arbiters.remove(index);
+        env.storage().instance().set(&DataKey::Arbiters, &arbiters);
+
+        env.events()
+            .publish(("ESCROW", "arbiter_removed"), ArbiterEvent { arbiter });
+        Ok(())
+    }
+
+    /// Set how many ledgers an arbiter has to resolve a dispute before anyone
+    /// may refund the customer.  Config admin only.  Applies to disputes opened
+    /// after the change.
+    pub fn set_dispute_timeout_ledgers(
+        env: Env,
+        caller: Address,
+        ledgers: u32,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::ConfigAdmin)?;
+
+        if ledgers == 0 || ledgers > MAX_TTL_LEDGERS {
+            return Err(EscrowError::InvalidConfig);
+        }
+        env.storage()
+            .instance()
+            .set(&DataKey::DisputeTimeoutLedgers, &ledgers);
+        Ok(())
+    }
+
+    pub fn get_dispute_timeout_ledgers(env: Env) -> Result<u32, EscrowError> {
+        Ok(env
+            .storage()
+            .instance()
+            .get(&DataKey::DisputeTimeoutLedgers)
+            .unwrap_or(DEFAULT_DISPUTE_TIMEOUT_LEDGERS))
+    }
+
+    pub fn get_arbiters(env: Env) -> Result<Vec<Address>, EscrowError> {
+        Ok(env
+            .storage()
+            .instance()
+            .get(&DataKey::Arbiters)
+            .unwrap_or(Vec::new(&env)))
+    }
+
+    /// Lock `amount` from the customer until it is released or refunded.
+    ///
+    /// With `auto_release_after` set, the customer has until that ledger to
+    /// open a dispute; after it anyone may `claim` the escrow for the merchant.
+    pub fn deposit(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        auto_release_after: Option<u32>,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            auto_release_after,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    /// Deposit on behalf of `customer` with funds taken from `payer`, e.g. a
+    /// custodial wallet or a sponsoring relayer.  Only `payer` authorizes the
+    /// call; `customer` keeps the customer's rights over the escrow.
+    ///
+    /// Refunds go to `refund_to`, or back to `payer` when it is `None`.
+    pub fn deposit_from(
+        env: Env,
+        payer: Address,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        refund_to: Option<Address>,
+    ) -> Result<BytesN<32>, EscrowError> {
+        let refund_to = Some(refund_to.unwrap_or(payer.clone())).filter(|to| *to != customer);
+        Self::create_escrow(
+            &env,
+            Funding::Transfer(payer),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            refund_to,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    /// Pay payment request `invoice_id` into escrow.  `merchant`, `amount` and
+    /// `asset_type` must match the request, which must still be pending and
+    /// unexpired; it is moved to `Confirmed` in the same transaction.
+    ///
+    /// Only the exact invoice amount is accepted, so an invoice is never left
+    /// under- or overpaid.  If the escrow later ends refunded (`Expired`), the
+    /// request is marked `Failed`.
+    pub fn deposit_invoice(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        invoice_id: String,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+    ) -> Result<BytesN<32>, EscrowError> {
+        let requests: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::InvoiceContract)
+            .ok_or(EscrowError::InvalidConfig)?;
+        let requests = PaymentRequestClient::new(&env, &requests);
+        let invoice = match requests.try_get_payment(&invoice_id) {
+            Ok(Ok(invoice)) => invoice,
+            _ => return Err(EscrowError::InvoiceNotFound),
+        };
+
+        if invoice.status != InvoiceStatus::Pending || env.ledger().timestamp() > invoice.expiry {
+            return Err(EscrowError::InvoiceNotPayable);
+        }
+        if merchant != invoice.merchant
+            || Self::token_address(&env, &asset_type) != invoice.asset
+            || amount != invoice.amount
+        {
+            return Err(EscrowError::InvoiceMismatch);
+        }
+
+        let payment_id = Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            None,
+            Some(invoice_id.clone()),
+            None,
+            None,
+            None,
+        )?;
+        requests.confirm(&env.current_contract_address(), &invoice_id);
+        Ok(payment_id)
+    }
+
+    /// Return where refunds for an escrow are sent: the configured refund
+    /// address, or the customer.
+    pub fn get_refund_address(env: Env, payment_id: BytesN<32>) -> Result<Address, EscrowError> {
+        let payment = Self::get_payment(env, payment_id)?;
+        Ok(Self::refund_address(&payment))
+    }
+
+    /// Deposit into an escrow whose merchant-side payouts are split across
+    /// several recipients (e.g. seller, platform commission, delivery partner).
+    ///
+    /// `merchant` stays the escrow's counterparty for registry checks and
+    /// disputes; every release pays the recipients instead, pro-rata to their
+    /// resolved shares.
+    pub fn deposit_split(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        recipients: Vec<SplitRecipientInput>,
+    ) -> Result<BytesN<32>, EscrowError> {
+        if amount <= 0 {
+            return Err(EscrowError::InvalidAmount);
+        }
+        let split = Self::resolve_split(&env, amount, &recipients)?;
+
+        let payment_id = Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
+        env.storage()
+            .persistent()
+            .set(&DataKey::Split(payment_id.clone()), &split);
+        Self::bump_payment(env, payment_id.clone())?;
+
+        Ok(payment_id)
+    }
+
+    /// Return the split recipients for an escrow, if it was created with
+    /// `deposit_split`.
+    pub fn get_split(
+        env: Env,
+        payment_id: BytesN<32>,
+    ) -> Result<Option<Vec<SplitRecipient>>, EscrowError> {
+        Ok(env.storage().persistent().get(&DataKey::Split(payment_id)))
+    }
+
+    /// Deposit into an escrow paid out milestone by milestone.
+    ///
+    /// The deposit amount is the sum of the milestone amounts.  Each milestone
+    /// is released by `approve_milestone`; once its `deadline` ledger has passed
+    /// without approval, anyone may call `settle_milestone` to apply
+    /// `on_deadline`.  Expiring the escrow applies it to any still pending.
+    pub fn deposit_milestones(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        milestones: Vec<MilestoneInput>,
+        on_deadline: MilestoneDefault,
+    ) -> Result<BytesN<32>, EscrowError> {
+        if milestones.is_empty() {
+            return Err(EscrowError::InvalidMilestones);
+        }
+        if milestones.len() > MAX_MILESTONES {
+            return Err(EscrowError::LimitExceeded);
+        }
+
+        let expiry = env.ledger().sequence().saturating_add(ttl_ledgers);
+        let mut amount: i128 = 0;
+        let mut schedule: Vec<Milestone> = Vec::new(&env);
+        for input in milestones.iter() {
+            if input.amount <= 0 {
+                return Err(EscrowError::InvalidAmount);
+            }
+            if input.deadline <= env.ledger().sequence() || input.deadline > expiry {
+                return Err(EscrowError::InvalidMilestones);
+            }
+            amount = amount.saturating_add(input.amount);
+            schedule.push_back(Milestone {
+                label: input.label,
+                amount: input.amount,
+                deadline: input.deadline,
+                status: MilestoneStatus::Pending,
+            });
+        }
+
+        let payment_id = Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
+        env.storage().persistent().set(
+            &DataKey::Milestones(payment_id.clone()),
+            &MilestonePlan {
+                milestones: schedule,
+                on_deadline,
+            },
+        );
+        Self::bump_payment(env, payment_id.clone())?;
+
+        Ok(payment_id)
+    }
+
+    /// Approve a milestone and pay it to the merchant.  Callable by the
+    /// customer or the operator.
+    pub fn approve_milestone(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        index: u32,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if caller != payment.customer {
+            Self::require_role(&env, &caller, Role::Operator)?;
+        }
+        Self::require_releasable(&env, &payment)?;
+
+        let mut plan = Self::get_milestone_plan(&env, &payment_id)?;
+        let milestone = Self::take_pending_milestone(&mut plan, index, MilestoneStatus::Released)?;
+
+        let prev_status = payment.status.clone();
+        Self::pay_merchant(&env, &payment, milestone.amount)?;
+        Self::finish_milestone(&env, &mut payment, &plan, milestone.amount);
+
+        Self::publish_transition(
+            &env,
+            "milestone_released",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            milestone.amount,
+            Some(index),
+        );
+        Ok(())
+    }
+
+    /// Apply the escrow's default outcome to a milestone whose deadline has
+    /// passed without approval.  Permissionless.
+    pub fn settle_milestone(
+        env: Env,
+        payment_id: BytesN<32>,
+        index: u32,
+    ) -> Result<(), EscrowError> {
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status == PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeOpen);
+        }
+        if payment.status != PaymentStatus::Pending {
+            return Err(EscrowError::PaymentNotPending);
+        }
+
+        let mut plan = Self::get_milestone_plan(&env, &payment_id)?;
+        let deadline = plan
+            .milestones
+            .get(index)
+            .ok_or(EscrowError::InvalidMilestones)?
+            .deadline;
+        if env.ledger().sequence() <= deadline {
+            return Err(EscrowError::MilestoneDeadlineNotReached);
+        }
+
+        let outcome = match plan.on_deadline {
+            MilestoneDefault::Release => MilestoneStatus::Released,
+            MilestoneDefault::Refund => MilestoneStatus::Refunded,
+        };
+        let milestone = Self::take_pending_milestone(&mut plan, index, outcome.clone())?;
+
+        let prev_status = payment.status.clone();
+        let (topic, recipient) = if outcome == MilestoneStatus::Released {
+            Self::pay_merchant(&env, &payment, milestone.amount)?;
+            ("milestone_released", payment.merchant.clone())
+        } else {
+            let refund_to = Self::refund_address(&payment);
+            Self::transfer_from_contract(&env, &refund_to, milestone.amount, &payment.token);
+            ("milestone_refunded", refund_to)
+        };
+        Self::finish_milestone(&env, &mut payment, &plan, milestone.amount);
+
+        Self::publish_transition(
+            &env,
+            topic,
+            &payment,
+            prev_status,
+            Some(recipient),
+            milestone.amount,
+            Some(index),
+        );
+        Ok(())
+    }
+
+    /// Return the milestone schedule for an escrow created with
+    /// `deposit_milestones`.
+    pub fn get_milestones(env: Env, payment_id: BytesN<32>) -> Result<MilestonePlan, EscrowError> {
+        Self::get_milestone_plan(&env, &payment_id)
+    }
+
+    /// Return a single milestone, including its current status.
+    pub fn get_milestone(
+        env: Env,
+        payment_id: BytesN<32>,
+        index: u32,
+    ) -> Result<Milestone, EscrowError> {
+        Self::get_milestone_plan(&env, &payment_id)?
+            .milestones
+            .get(index)
+            .ok_or(EscrowError::InvalidMilestones)
+    }
+
+    /// Lock `amount` to vest linearly to the merchant from `start_ledger` to
+    /// `end_ledger`, e.g. for payroll or rent.  The merchant collects what has
+    /// vested with `withdraw_available`; the customer may `cancel_stream` to
+    /// take back what has not.
+    ///
+    /// `end_ledger` must fall before the escrow expires.  Whatever the
+    /// merchant has not withdrawn by then is paid out by `expire`.
+    pub fn deposit_stream(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        start_ledger: u32,
+        end_ledger: u32,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            None,
+            None,
+            Some((start_ledger, end_ledger)),
+            None,
+            None,
+        )
+    }
+
+    /// Pay the merchant everything a stream has vested that has not been
+    /// paid out yet, and return that amount.  Merchant only.
+    pub fn withdraw_available(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+    ) -> Result<i128, EscrowError> {
+        caller.require_auth();
+
+        let mut payment = Self::get_payment(env.clone(), payment_id)?;
+        if caller != payment.merchant {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        Self::require_releasable(&env, &payment)?;
+        let available = Self::withdrawable_amount(&env, &payment)?;
+        if available <= 0 {
+            return Err(EscrowError::StreamBalanceEmpty);
+        }
+
+        let prev_status = payment.status.clone();
+        Self::pay_merchant(&env, &payment, available)?;
+        payment.released_amount += available;
+        if payment.released_amount == payment.amount {
+            payment.status = PaymentStatus::Released;
+        }
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "withdraw",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            available,
+            None,
+        );
+        Ok(available)
+    }
+
+    /// Stop a stream early and refund its unvested balance.  Customer only.
+    ///
+    /// What has already vested stays in escrow for the merchant to withdraw,
+    /// so a release pause or an unverified merchant never holds up the refund.
+    pub fn cancel_stream(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let mut payment = Self::get_payment(env.clone(), payment_id)?;
+        if caller != payment.customer {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        Self::require_releasable(&env, &payment)?;
+        let owed = Self::withdrawable_amount(&env, &payment)?;
+        let unvested = Self::remaining_amount(&payment) - owed;
+        if unvested <= 0 {
+            return Err(EscrowError::StreamBalanceEmpty);
+        }
+
+        let prev_status = payment.status.clone();
+        let refund_to = Self::refund_address(&payment);
+        Self::transfer_from_contract(&env, &refund_to, unvested, &payment.token);
+        payment.released_amount += unvested;
+        if owed > 0 {
+            // Ending the stream now leaves exactly `owed` vested and unpaid.
+            payment.stream_end = Some(env.ledger().sequence());
+        } else {
+            payment.status = PaymentStatus::Expired;
+        }
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "stream_cancelled",
+            &payment,
+            prev_status,
+            Some(refund_to),
+            unvested,
+            None,
+        );
+        Ok(())
+    }
+
+    /// Return how much of a stream the merchant could withdraw now.
+    pub fn get_withdrawable(env: Env, payment_id: BytesN<32>) -> Result<i128, EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id)?;
+        Self::withdrawable_amount(&env, &payment)
+    }
+
+    /// Lock `amount` for a cross-chain swap.  Anyone who reveals the preimage
+    /// of `hashlock` (its SHA-256 digest) to `claim_with_preimage` before the
+    /// escrow expires pays the merchant; after that, `refund` returns the
+    /// funds to the customer.
+    ///
+    /// Hashlocked escrows cannot be disputed or released by the operator.
+    pub fn deposit_htlc(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        hashlock: BytesN<32>,
+        timeout_ledgers: u32,
+        asset_type: AssetType,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            timeout_ledgers,
+            asset_type,
+            None,
+            None,
+            None,
+            None,
+            Some(hashlock),
+            None,
+        )
+    }
+
+    /// Pay a hashlocked escrow to the merchant by revealing the preimage of
+    /// its hashlock.  Permissionless; the preimage is published so the
+    /// counterparty chain can complete the swap.
+    ///
+    /// Once the preimage is public the swap must complete, so a valid claim
+    /// skips the release pause, KYC check and fee hooks that could otherwise
+    /// revert it and leave the customer free to refund.  For the same reason
+    /// its settlement ledger write is best effort.
+    pub fn claim_with_preimage(
+        env: Env,
+        payment_id: BytesN<32>,
+        preimage: Bytes,
+    ) -> Result<(), EscrowError> {
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        let hashlock = payment
+            .hashlock
+            .clone()
+            .ok_or(EscrowError::UnsupportedEscrowKind)?;
+        if BytesN::from(env.crypto().sha256(&preimage)) != hashlock {
+            return Err(EscrowError::PreimageMismatch);
+        }
+        Self::require_releasable(&env, &payment)?;
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+
+        // Hashlocked escrows never carry a split, so the merchant takes it all.
+        let prev_status = payment.status.clone();
+        Self::transfer_from_contract(&env, &payment.merchant, remaining, &payment.token);
+        Self::try_record_settlement(&env, &payment, remaining);
+        payment.released_amount = payment.amount;
+        payment.status = PaymentStatus::Released;
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "release",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            remaining,
+            None,
+        );
+        env.events().publish(
+            ("ESCROW", "preimage"),
+            PreimageEvent {
+                payment_id,
+                hashlock,
+                preimage,
+            },
+        );
+        Ok(())
+    }
+
+    /// Lock `amount` until `attester`, an ed25519 key such as a logistics
+    /// webhook signer, attests that `condition_hash` has been met.  The
+    /// operator can still release or refund as for any other escrow.
+    pub fn deposit_attested(
+        env: Env,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        attester: BytesN<32>,
+        condition_hash: BytesN<32>,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Self::create_escrow(
+            &env,
+            Funding::Transfer(customer.clone()),
+            customer,
+            payment_id,
+            merchant,
+            amount,
+            ttl_ledgers,
+            asset_type,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some((attester, condition_hash)),
+        )
+    }
+
+    /// Pay the merchant on the attester's ed25519 `signature` over the XDR of
+    /// the escrow's `AttestationPayload` for `condition_hash`.  Anyone may
+    /// submit the attestation; the call traps if the signature is invalid.
+    pub fn release_attested(
+        env: Env,
+        payment_id: BytesN<32>,
+        condition_hash: BytesN<32>,
+        signature: BytesN<64>,
+    ) -> Result<(), EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        let attester = payment.attester.clone().ok_or(EscrowError::UnsupportedEscrowKind)?;
+        if payment.condition_hash.as_ref() != Some(&condition_hash) {
+            return Err(EscrowError::ConditionMismatch);
+        }
+        let payload = Self::attestation_payload(&env, payment_id.clone(), condition_hash.clone());
+        env.crypto()
+            .ed25519_verify(&attester, &payload.to_xdr(&env), &signature);
+
+        Self::release_remaining(&env, payment)?;
+        env.events().publish(
+            ("ESCROW", "attested"),
+            AttestationEvent {
+                payment_id,
+                attester,
+                condition_hash,
+            },
+        );
+        Ok(())
+    }
+
+    /// Return the payload an attester signs to release `payment_id`.
+    pub fn get_attestation_payload(
+        env: Env,
+        payment_id: BytesN<32>,
+    ) -> Result<AttestationPayload, EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        let condition_hash = payment.condition_hash.ok_or(EscrowError::UnsupportedEscrowKind)?;
+        Ok(Self::attestation_payload(&env, payment_id, condition_hash))
+    }
+
+    /// Authorize `merchant` to be paid `amount_per_period` every
+    /// `period_ledgers` until `end_ledger`, starting now.  Each period is
+    /// pulled into its own escrow by `charge`, which anyone may call once the
+    /// period is due.
+    ///
+    /// The customer's allowance to this contract is raised by the total of
+    /// all periods; `cancel_subscription` takes back what was not charged.
+    pub fn subscribe(
+        env: Env,
+        customer: Address,
+        subscription_id: BytesN<32>,
+        merchant: Address,
+        amount_per_period: i128,
+        period_ledgers: u32,
+        end_ledger: u32,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+    ) -> Result<(), EscrowError> {
+        customer.require_auth();
+
+        if amount_per_period <= 0 {
+            return Err(EscrowError::InvalidAmount);
+        }
+        let now = env.ledger().sequence();
+        if period_ledgers == 0
+            || end_ledger <= now
+            || end_ledger > env.ledger().max_live_until_ledger()
+            || ttl_ledgers == 0
+            || ttl_ledgers > MAX_TTL_LEDGERS
+        {
+            return Err(EscrowError::InvalidTtl);
+        }
+        let key = DataKey::Subscription(subscription_id.clone());
+        if env.storage().persistent().has(&key) {
+            return Err(EscrowError::SubscriptionAlreadyExists);
+        }
+
+        let token = Self::token_address(&env, &asset_type);
+        Self::require_within_token_limits(&env, &asset_type, &token, amount_per_period)?;
+        let periods = (end_ledger - now).div_ceil(period_ledgers);
+        let total = amount_per_period
+            .checked_mul(periods as i128)
+            .ok_or(EscrowError::InvalidAmount)?;
+        Self::adjust_allowance(&env, &token, &customer, total);
+
+        let subscription = Subscription {
+            subscription_id: subscription_id.clone(),
+            customer: customer.clone(),
+            merchant: merchant.clone(),
+            asset_type,
+            token,
+            amount_per_period,
+            period_ledgers,
+            end_ledger,
+            ttl_ledgers,
+            next_charge_ledger: now,
+            periods_charged: 0,
+            status: SubscriptionStatus::Active,
+        };
+        Self::save_subscription(&env, &subscription);
+
+        env.events().publish(
+            ("ESCROW", "subscribed"),
+            SubscriptionEvent {
+                subscription_id,
+                customer,
+                merchant,
+                amount_per_period,
+                period_ledgers,
+                end_ledger,
+            },
+        );
+        Ok(())
+    }
+
+    /// Pull the next due period of a subscription into a new escrow for the
+    /// merchant and return its payment id.  Anyone may call this; periods
+    /// missed earlier are charged one call at a time, including those that
+    /// fell due before the subscription was cancelled.
+    pub fn charge(env: Env, subscription_id: BytesN<32>) -> Result<BytesN<32>, EscrowError> {
+        let mut subscription = Self::get_subscription(env.clone(), subscription_id.clone())?;
+        if subscription.next_charge_ledger >= subscription.end_ledger {
+            return Err(EscrowError::SubscriptionNotActive);
+        }
+        if env.ledger().sequence() < subscription.next_charge_ledger {
+            return Err(EscrowError::SubscriptionNotDue);
+        }
+
+        let period = subscription.periods_charged;
+        let payment_id = Self::create_escrow(
+            &env,
+            Funding::Allowance,
+            subscription.customer.clone(),
+            Self::subscription_payment_id(&env, &subscription_id, period),
+            subscription.merchant.clone(),
+            subscription.amount_per_period,
+            subscription.ttl_ledgers,
+            subscription.asset_type.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
+
+        subscription.periods_charged += 1;
+        subscription.next_charge_ledger = subscription
+            .next_charge_ledger
+            .saturating_add(subscription.period_ledgers);
+        if subscription.next_charge_ledger >= subscription.end_ledger
+            && subscription.status == SubscriptionStatus::Active
+        {
+            subscription.status = SubscriptionStatus::Ended;
+        }
+        Self::save_subscription(&env, &subscription);
+
+        env.events().publish(
+            ("ESCROW", "sub_charged"),
+            SubscriptionChargeEvent {
+                subscription_id,
+                payment_id: payment_id.clone(),
+                period,
+                amount: subscription.amount_per_period,
+            },
+        );
+        Ok(payment_id)
+    }
+
+    /// Stop a subscription and withdraw the allowance for the periods that
+    /// have not started yet.  Periods already due stay chargeable, so a
+    /// cancel cannot skip what the customer has used, and escrows already
+    /// charged are unaffected.  Customer only.
+    pub fn cancel_subscription(
+        env: Env,
+        caller: Address,
+        subscription_id: BytesN<32>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let mut subscription = Self::get_subscription(env.clone(), subscription_id.clone())?;
+        if caller != subscription.customer {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        if subscription.status != SubscriptionStatus::Active {
+            return Err(EscrowError::SubscriptionNotActive);
+        }
+
+        let now = env.ledger().sequence();
+        let next = subscription.next_charge_ledger;
+        let uncharged = (subscription.end_ledger - next).div_ceil(subscription.period_ledgers);
+        let due = if now >= next {
+            ((now - next) / subscription.period_ledgers + 1).min(uncharged)
+        } else {
+            0
+        };
+        // Pull the end in to just after the last due period.
+        subscription.end_ledger = subscription
+            .end_ledger
+            .min(next.saturating_add(due * subscription.period_ledgers));
+        let released_allowance = subscription.amount_per_period * (uncharged - due) as i128;
+        Self::adjust_allowance(
+            &env,
+            &subscription.token,
+            &subscription.customer,
+            -released_allowance,
+        );
+        subscription.status = SubscriptionStatus::Cancelled;
+        Self::save_subscription(&env, &subscription);
+
+        env.events().publish(
+            ("ESCROW", "sub_cancelled"),
+            SubscriptionCancelEvent {
+                subscription_id,
+                periods_charged: subscription.periods_charged,
+                released_allowance,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get_subscription(
+        env: Env,
+        subscription_id: BytesN<32>,
+    ) -> Result<Subscription, EscrowError> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Subscription(subscription_id))
+            .ok_or(EscrowError::SubscriptionNotFound)
+    }
+
+    /// Payment id of the escrow that `charge` creates for `period` (counting
+    /// from zero) of a subscription.
+    pub fn get_subscription_payment_id(
+        env: Env,
+        subscription_id: BytesN<32>,
+        period: u32,
+    ) -> Result<BytesN<32>, EscrowError> {
+        Ok(Self::subscription_payment_id(&env, &subscription_id, period))
+    }
+
+    fn create_escrow(
+        env: &Env,
+        funding: Funding,
+        customer: Address,
+        payment_id: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+        ttl_ledgers: u32,
+        asset_type: AssetType,
+        refund_to: Option<Address>,
+        auto_release_after: Option<u32>,
+        invoice_id: Option<String>,
+        stream: Option<(u32, u32)>,
+        hashlock: Option<BytesN<32>>,
+        attestation: Option<(BytesN<32>, BytesN<32>)>,
+    ) -> Result<BytesN<32>, EscrowError> {
+        if let Funding::Transfer(payer) = &funding {
+            payer.require_auth();
+        }
+
+        if amount <= 0 {
+            return Err(EscrowError::InvalidAmount);
+        }
+        if ttl_ledgers == 0 || ttl_ledgers > MAX_TTL_LEDGERS {
+            return Err(EscrowError::InvalidTtl);
+        }
+        let expiry = env.ledger().sequence().saturating_add(ttl_ledgers);
+        if let Some(ledger) = auto_release_after {
+            if ledger <= env.ledger().sequence() || ledger >= expiry {
+                return Err(EscrowError::InvalidTtl);
+            }
+        }
+        if let Some((start, end)) = stream {
+            if start < env.ledger().sequence() || end <= start || end >= expiry {
+                return Err(EscrowError::InvalidTtl);
+            }
+        }
+
+        if !Self::drained_assets(env).is_empty() {
+            return Err(EscrowError::PaymentFrozen);
+        }
+        if Self::is_paused(env.clone(), PauseScope::Deposits)? {
+            return Err(EscrowError::DepositsPaused);
+        }
+        if Self::is_paused(env.clone(), PauseScope::Merchant(merchant.clone()))? {
+            return Err(EscrowError::DepositsPaused);
+        }
+
+        // Tagged ids belong to `charge`; refusing them here keeps a deposit
+        // from taking a subscription period's id before it is charged.
+        let reserved = matches!(funding, Funding::Transfer(_))
+            && Self::is_subscription_payment_id(&payment_id);
+        let key = DataKey::Payment(payment_id.clone());
+        if reserved || env.storage().persistent().has(&key) {
+            return Err(EscrowError::PaymentAlreadyExists);
+        }
+
+        // If a registry contract is configured, verify the merchant is active.
+        if let Some(registry_addr) = env
+            .storage()
+            .instance()
+            .get::<DataKey, Address>(&DataKey::RegistryContract)
+        {
+            let registry_client = MerchantRegistryClient::new(env, &registry_addr);
+            if !registry_client.is_merchant_active(&merchant) {
+                return Err(EscrowError::MerchantNotActive);
+            }
+        }
+
+        // Transfer funds into the contract via the appropriate token interface.
+        let token_addr = Self::token_address(env, &asset_type);
+        Self::require_within_token_limits(env, &asset_type, &token_addr, amount)?;
+        let token_client = token::Client::new(env, &token_addr);
+        let escrow = env.current_contract_address();
+        match funding {
+            Funding::Transfer(payer) => token_client.transfer(&payer, &escrow, &amount),
+            Funding::Allowance => token_client.transfer_from(&escrow, &customer, &escrow, &amount),
+        }
+
+        let dispute_window_end = {
+            let max_window_end = env
+                .ledger()
+                .sequence()
+                .saturating_add(MAX_DISPUTE_WINDOW_LEDGERS);
+            if expiry < max_window_end {
+                expiry
+            } else {
+                max_window_end
+            }
+        };
+        // Disputes must be opened before the escrow becomes claimable.
+        let dispute_window_end =
+            auto_release_after.map_or(dispute_window_end, |ledger| dispute_window_end.min(ledger));
+
+        let payment = PaymentEscrow {
+            payment_id: payment_id.clone(),
+            amount,
+            released_amount: 0,
+            merchant: merchant.clone(),
+            customer: customer.clone(),
+            status: PaymentStatus::Pending,
+            expiry,
+            dispute_window_end,
+            dispute_reason: None,
+            asset_type,
+            token: token_addr,
+            refund_to,
+            auto_release_after,
+            invoice_id,
+            stream_start: stream.map(|(start, _)| start),
+            stream_end: stream.map(|(_, end)| end),
+            hashlock,
+            attester: attestation.clone().map(|(attester, _)| attester),
+            condition_hash: attestation.map(|(_, condition_hash)| condition_hash),
+        };
+
+        Self::save_payment(env, &payment);
+        for index in [
+            EscrowIndex::Merchant(payment.merchant.clone()),
+            EscrowIndex::Customer(payment.customer.clone()),
+            EscrowIndex::Expiry(expiry / EXPIRY_BUCKET_LEDGERS),
+        ] {
+            Self::append_to_index(env, index, &payment);
+        }
+
+        Self::publish_transition(
+            env,
+            "deposit",
+            &payment,
+            PaymentStatus::Pending,
+            None,
+            amount,
+            None,
+        );
+
+        Ok(payment_id)
+    }
+
+    pub fn release(env: Env, caller: Address, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::Operator)?;
+
+        Self::release_payment(&env, payment_id)?;
+        Ok(())
+    }
+
+    /// Pay the merchant once an escrow's `auto_release_after` ledger has
+    /// passed without a dispute.  Permissionless.
+    pub fn claim(env: Env, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        match payment.auto_release_after {
+            Some(ledger) if env.ledger().sequence() > ledger => {}
+            _ => return Err(EscrowError::AutoReleaseNotDue),
+        }
+
+        Self::release_payment(&env, payment_id)?;
+        Ok(())
+    }
+
+    /// Release several payments in one call.  Operator only.
+    ///
+    /// Payments that cannot be released are skipped and reported in the
+    /// result instead of aborting the batch.
+    pub fn release_batch(
+        env: Env,
+        caller: Address,
+        payment_ids: Vec<BytesN<32>>,
+    ) -> Result<Vec<BatchItemResult>, EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::Operator)?;
+        Self::require_releases_open(&env)?;
+
+        Self::run_batch(&env, payment_ids, Self::release_payment)
+    }
+
+    pub fn release_partial(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        amount: i128,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_role(&env, &caller, Role::Operator)?;
+
+        if amount <= 0 {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        Self::require_releasable(&env, &payment)?;
+        Self::require_no_milestones(&env, &payment_id)?;
+        Self::require_lump_sum(&payment)?;
+
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+        if amount > remaining {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let prev_status = payment.status.clone();
+        Self::pay_merchant(&env, &payment, amount)?;
+        payment.released_amount += amount;
+        if payment.released_amount == payment.amount {
+            payment.status = PaymentStatus::Released;
+        }
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "release",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            amount,
+            None,
+        );
+        Ok(())
+    }
+
+    /// Release the remaining balance to the merchant on the customer's own
+    /// authorization, e.g. once delivery has been confirmed.
+    pub fn customer_release(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if caller != payment.customer {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        Self::require_releasable(&env, &payment)?;
+        Self::require_no_milestones(&env, &payment_id)?;
+
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+
+        let prev_status = payment.status.clone();
+        Self::pay_merchant(&env, &payment, remaining)?;
+        payment.released_amount = payment.amount;
+        payment.status = PaymentStatus::Released;
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "customer_release",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            remaining,
+            None,
+        );
+        Ok(())
+    }
+
+    /// Refund some or all of the remaining balance to the customer on the
+    /// merchant's authorization, e.g. to cancel an order before expiry.
+    ///
+    /// Milestone escrows can only be refunded in full.
+    pub fn merchant_refund(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        amount: i128,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
 
         if amount <= 0 {
-            panic!("Amount must be > 0");
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if caller != payment.merchant {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        Self::require_releasable(&env, &payment)?;
+
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+        if amount > remaining {
+            return Err(EscrowError::InvalidAmount);
+        }
+        if Self::has_milestones(&env, &payment_id) && amount != remaining {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let prev_status = payment.status.clone();
+        let refund_to = Self::refund_address(&payment);
+        Self::transfer_from_contract(&env, &refund_to, amount, &payment.token);
+        payment.released_amount += amount;
+        if payment.released_amount == payment.amount {
+            Self::close_open_milestones(&env, &payment_id, MilestoneStatus::Refunded);
+            payment.status = PaymentStatus::Expired;
+        }
+        Self::save_payment(&env, &payment);
+        Self::fail_invoice(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "merchant_refund",
+            &payment,
+            prev_status,
+            Some(refund_to),
+            amount,
+            None,
+        );
+        Ok(())
+    }
+
+    pub fn expire(env: Env, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        Self::refund_payment(&env, payment_id, "expired")?;
+        Ok(())
+    }
+
+    pub fn refund(env: Env, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        Self::refund_payment(&env, payment_id, "refund")?;
+        Ok(())
+    }
+
+    /// Expire several payments in one call, skipping any that are not
+    /// expirable.
+    pub fn expire_batch(
+        env: Env,
+        payment_ids: Vec<BytesN<32>>,
+    ) -> Result<Vec<BatchItemResult>, EscrowError> {
+        Self::run_batch(&env, payment_ids, |env, payment_id| {
+            Self::refund_payment(env, payment_id, "expired")
+        })
+    }
+
+    /// Refund several payments in one call, skipping any that are not
+    /// expirable.
+    pub fn refund_batch(
+        env: Env,
+        payment_ids: Vec<BytesN<32>>,
+    ) -> Result<Vec<BatchItemResult>, EscrowError> {
+        Self::run_batch(&env, payment_ids, |env, payment_id| {
+            Self::refund_payment(env, payment_id, "refund")
+        })
+    }
+
+    pub fn get_max_batch_size(env: Env) -> Result<u32, EscrowError> {
+        Ok(Self::max_batch_size(&env))
+    }
+
+    pub fn dispute(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        reason: String,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status == PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeOpen);
+        }
+        if payment.status != PaymentStatus::Pending {
+            return Err(EscrowError::PaymentNotPending);
+        }
+        if caller != payment.customer && caller != payment.merchant {
+            return Err(EscrowError::NotPaymentParty);
+        }
+        // A swap must stay claimable by preimage until it times out.
+        if payment.hashlock.is_some() {
+            return Err(EscrowError::UnsupportedEscrowKind);
+        }
+        if env.ledger().sequence() > payment.dispute_window_end {
+            return Err(EscrowError::DisputeWindowExpired);
+        }
+
+        let deadline = env
+            .ledger()
+            .sequence()
+            .saturating_add(Self::get_dispute_timeout_ledgers(env.clone())?);
+        env.storage()
+            .persistent()
+            .set(&DataKey::DisputeDeadline(payment_id.clone()), &deadline);
+
+        let prev_status = payment.status.clone();
+        payment.status = PaymentStatus::Disputed;
+        payment.dispute_reason = Some(reason);
+        Self::save_payment(&env, &payment);
+
+        Self::publish_transition(
+            &env,
+            "dispute",
+            &payment,
+            prev_status,
+            None,
+            Self::remaining_amount(&payment),
+            None,
+        );
+        Ok(())
+    }
+
+    pub fn resolve_dispute(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        winner: Address,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_arbiter(&env, &caller)?;
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status != PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeNotOpen);
+        }
+        if winner != payment.customer && winner != payment.merchant {
+            return Err(EscrowError::NotPaymentParty);
+        }
+
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+
+        let prev_status = payment.status.clone();
+        let refund_to = Self::refund_address(&payment);
+        let (merchant_amount, customer_amount) = if winner == payment.merchant {
+            Self::pay_merchant(&env, &payment, remaining)?;
+            Self::close_open_milestones(&env, &payment_id, MilestoneStatus::Released);
+            (remaining, 0)
+        } else {
+            Self::transfer_from_contract(&env, &refund_to, remaining, &payment.token);
+            Self::close_open_milestones(&env, &payment_id, MilestoneStatus::Refunded);
+            (0, remaining)
+        };
+        payment.status = if winner == payment.merchant {
+            PaymentStatus::Released
+        } else {
+            PaymentStatus::Expired
+        };
+        payment.released_amount = payment.amount;
+        Self::save_payment(&env, &payment);
+        Self::fail_invoice(&env, &payment);
+
+        Self::publish_dispute_resolved(
+            &env,
+            &payment,
+            prev_status,
+            refund_to,
+            merchant_amount,
+            customer_amount,
+        );
+        Ok(())
+    }
+
+    /// Settle a dispute by dividing the remaining balance between merchant and
+    /// customer, e.g. 70 % / 30 %.  The two amounts must add up to the
+    /// remaining balance.
+    pub fn resolve_dispute_split(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        merchant_amount: i128,
+        customer_amount: i128,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_arbiter(&env, &caller)?;
+
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status != PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeNotOpen);
+        }
+        if merchant_amount < 0 || customer_amount < 0 {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
+        }
+        if merchant_amount.saturating_add(customer_amount) != remaining {
+            return Err(EscrowError::ResolutionMismatch);
+        }
+
+        if merchant_amount > 0 {
+            Self::pay_merchant(&env, &payment, merchant_amount)?;
+        }
+        let refund_to = Self::refund_address(&payment);
+        if customer_amount > 0 {
+            Self::transfer_from_contract(&env, &refund_to, customer_amount, &payment.token);
         }
-        if ttl_ledgers == 0 {
-            panic!("TTL must be > 0");
+        Self::close_open_milestones(&env, &payment_id, MilestoneStatus::Resolved);
+
+        let prev_status = payment.status.clone();
+        payment.released_amount = payment.amount;
+        payment.status = PaymentStatus::Resolved;
+        Self::save_payment(&env, &payment);
+
+        Self::publish_dispute_resolved(
+            &env,
+            &payment,
+            prev_status,
+            refund_to,
+            merchant_amount,
+            customer_amount,
+        );
+        Ok(())
+    }
+
+    /// Refund the customer when a dispute has not been resolved by its
+    /// deadline.  Anyone may call this.
+    pub fn timeout_dispute(env: Env, payment_id: BytesN<32>) -> Result<(), EscrowError> {
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status != PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeNotOpen);
         }
-        if ttl_ledgers > MAX_TTL_LEDGERS {
-            panic!("TTL exceeds maximum");
+        let deadline = match Self::get_dispute_deadline(env.clone(), payment_id.clone())? {
+            Some(deadline) => deadline,
+            None => return Err(EscrowError::DisputeDeadlineNotReached),
+        };
+        if env.ledger().sequence() <= deadline {
+            return Err(EscrowError::DisputeDeadlineNotReached);
         }
 
-        let key = DataKey::Payment(payment_id.clone());
-        if env.storage().persistent().has(&key) {
-            panic!("Payment ID already exists");
+        let remaining = Self::remaining_amount(&payment);
+        let refund_to = Self::refund_address(&payment);
+        if remaining > 0 {
+            Self::transfer_from_contract(&env, &refund_to, remaining, &payment.token);
         }
+        Self::close_open_milestones(&env, &payment_id, MilestoneStatus::Refunded);
+        let prev_status = payment.status.clone();
+        payment.released_amount = payment.amount;
+        payment.status = PaymentStatus::Expired;
+        Self::save_payment(&env, &payment);
+        Self::fail_invoice(&env, &payment);
 
-        // If a registry contract is configured, verify the merchant is approved.
-        if let Some(registry_addr) = env
+        env.events().publish(
+            ("ESCROW", "dispute_timeout"),
+            DisputeTimeoutEvent {
+                version: EVENT_SCHEMA_VERSION,
+                payment_id,
+                asset: payment.token.clone(),
+                customer: payment.customer.clone(),
+                merchant: payment.merchant.clone(),
+                recipient: refund_to,
+                amount: remaining,
+                deadline,
+                prev_status,
+                status: payment.status.clone(),
+                released_amount: payment.released_amount,
+                dispute_reason: payment.dispute_reason.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Ledger after which an open dispute can be timed out, if one was opened.
+    pub fn get_dispute_deadline(
+        env: Env,
+        payment_id: BytesN<32>,
+    ) -> Result<Option<u32>, EscrowError> {
+        Ok(env
+            .storage()
+            .persistent()
+            .get(&DataKey::DisputeDeadline(payment_id)))
+    }
+
+    /// Append an evidence hash to an open dispute.  Either party may submit,
+    /// up to `MAX_EVIDENCE_PER_PARTY` entries each.
+    pub fn submit_evidence(
+        env: Env,
+        caller: Address,
+        payment_id: BytesN<32>,
+        hash: BytesN<32>,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+
+        let payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        if payment.status != PaymentStatus::Disputed {
+            return Err(EscrowError::DisputeNotOpen);
+        }
+        if caller != payment.customer && caller != payment.merchant {
+            return Err(EscrowError::NotPaymentParty);
+        }
+
+        let key = DataKey::Evidence(payment_id.clone());
+        let mut log: Vec<EvidenceEntry> = env
+            .storage()
+            .persistent()
+            .get(&key)
+            .unwrap_or(Vec::new(&env));
+        let submitted = log.iter().filter(|entry| entry.submitter == caller).count();
+        if submitted as u32 >= MAX_EVIDENCE_PER_PARTY {
+            return Err(EscrowError::LimitExceeded);
+        }
+        log.push_back(EvidenceEntry {
+            hash: hash.clone(),
+            submitter: caller.clone(),
+            timestamp: env.ledger().timestamp(),
+        });
+        env.storage().persistent().set(&key, &log);
+        Self::bump_payment(env.clone(), payment_id.clone())?;
+
+        env.events().publish(
+            ("ESCROW", "evidence"),
+            EvidenceEvent {
+                payment_id,
+                submitter: caller,
+                hash,
+            },
+        );
+        Ok(())
+    }
+
+    /// Return up to `limit` evidence entries for a payment, oldest first,
+    /// starting at index `start`.
+    pub fn get_evidence(
+        env: Env,
+        payment_id: BytesN<32>,
+        start: u32,
+        limit: u32,
+    ) -> Result<Vec<EvidenceEntry>, EscrowError> {
+        let log: Vec<EvidenceEntry> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Evidence(payment_id))
+            .unwrap_or(Vec::new(&env));
+        let end = start.saturating_add(limit).min(log.len());
+        if start >= end {
+            return Ok(Vec::new(&env));
+        }
+        Ok(log.slice(start..end))
+    }
+
+    pub fn get_evidence_count(env: Env, payment_id: BytesN<32>) -> Result<u32, EscrowError> {
+        Ok(env
+            .storage()
+            .persistent()
+            .get::<_, Vec<EvidenceEntry>>(&DataKey::Evidence(payment_id))
+            .map(|log| log.len())
+            .unwrap_or(0))
+    }
+
+    pub fn get_payment(env: Env, payment_id: BytesN<32>) -> Result<PaymentEscrow, EscrowError> {
+        Self::load_payment(&env, payment_id).ok_or(EscrowError::PaymentNotFound)
+    }
+
+    /// List a merchant's escrows, oldest first, optionally only those in
+    /// `status`.  Reads at most `limit` index positions from `start`.
+    pub fn get_merchant_payments(
+        env: Env,
+        merchant: Address,
+        status: Option<PaymentStatus>,
+        start: u32,
+        limit: u32,
+    ) -> Result<EscrowPage, EscrowError> {
+        Ok(Self::read_index(&env, EscrowIndex::Merchant(merchant), start, limit, |payment| {
+            status.is_none() || status == Some(payment.status.clone())
+        }))
+    }
+
+    /// List a customer's escrows, oldest first, optionally only those in
+    /// `status`.  Reads at most `limit` index positions from `start`.
+    pub fn get_customer_payments(
+        env: Env,
+        customer: Address,
+        status: Option<PaymentStatus>,
+        start: u32,
+        limit: u32,
+    ) -> Result<EscrowPage, EscrowError> {
+        Ok(Self::read_index(&env, EscrowIndex::Customer(customer), start, limit, |payment| {
+            status.is_none() || status == Some(payment.status.clone())
+        }))
+    }
+
+    /// List the escrows in an expiry bucket that are still pending and past
+    /// expiry, i.e. ready for `expire`.  Keepers walk the buckets up to
+    /// `get_expiry_bucket(current ledger)`.
+    pub fn get_expired_payments(
+        env: Env,
+        bucket: u32,
+        start: u32,
+        limit: u32,
+    ) -> Result<EscrowPage, EscrowError> {
+        let now = env.ledger().sequence();
+        Ok(Self::read_index(&env, EscrowIndex::Expiry(bucket), start, limit, |payment| {
+            payment.status == PaymentStatus::Pending && now > payment.expiry
+        }))
+    }
+
+    /// Number of escrows ever appended to `index`.
+    pub fn get_index_len(env: Env, index: EscrowIndex) -> Result<u32, EscrowError> {
+        Ok(env
+            .storage()
+            .persistent()
+            .get(&DataKey::IndexLen(index))
+            .unwrap_or(0))
+    }
+
+    /// Expiry bucket that `ledger` falls into.
+    pub fn get_expiry_bucket(_env: Env, ledger: u32) -> Result<u32, EscrowError> {
+        Ok(ledger / EXPIRY_BUCKET_LEDGERS)
+    }
+
+    pub fn get_balance(env: Env, payment_id: BytesN<32>) -> Result<i128, EscrowError> {
+        let payment = Self::get_payment(env, payment_id)?;
+        Ok(Self::remaining_amount(&payment))
+    }
+
+    pub fn get_expiry(env: Env, payment_id: BytesN<32>) -> Result<u32, EscrowError> {
+        Ok(Self::get_payment(env, payment_id)?.expiry)
+    }
+
+    pub fn get_admin(env: Env) -> Result<Address, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::Admin).unwrap())
+    }
+
+    pub fn get_xlm_token(env: Env) -> Result<Address, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::XlmToken).unwrap())
+    }
+
+    pub fn get_usdc_token(env: Env) -> Result<Address, EscrowError> {
+        Ok(env.storage().instance().get(&DataKey::UsdcToken).unwrap())
+    }
+
+    pub fn get_default_ttl_ledgers(env: Env) -> Result<u32, EscrowError> {
+        Ok(env
             .storage()
             .instance()
-            .get::<DataKey, Address>(&DataKey::RegistryContract)
+            .get(&DataKey::DefaultTtlLedgers)
+            .unwrap())
+    }
+
+    pub fn get_max_dispute_window_ledgers(_env: Env) -> Result<u32, EscrowError> {
+        Ok(MAX_DISPUTE_WINDOW_LEDGERS)
+    }
+
+    pub fn get_max_ttl_ledgers(_env: Env) -> Result<u32, EscrowError> {
+        Ok(MAX_TTL_LEDGERS)
+    }
+
+    /// Move the full balance of each selected asset to the treasury.
+    /// `signers` must reach the configured emergency threshold.
+    ///
+    /// Drained assets stay frozen until `restore_asset` is called: open
+    /// escrows in them report `Frozen` and no new deposits are accepted in
+    /// any asset.  Returns the amount drained per asset, in input order.
+    pub fn emergency_drain(
+        env: Env,
+        caller: Address,
+        signers: Vec<Address>,
+        assets: Vec<AssetType>,
+    ) -> Result<Vec<i128>, EscrowError> {
+        caller.require_auth();
+        Self::require_emergency_quorum(&env, &caller, &signers)?;
+
+        let current_ledger = env.ledger().sequence();
+        let last_drain_ledger: u32 = env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencyLastDrainLedger)
+            .unwrap_or(0);
+        let cooldown_ledgers: u32 = env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencyCooldownLedgers)
+            .unwrap();
+        if last_drain_ledger != 0
+            && current_ledger < last_drain_ledger.saturating_add(cooldown_ledgers)
         {
-            let registry_client = MerchantRegistryClient::new(&env, &registry_addr);
-            if !registry_client.is_approved(&merchant) {
-                panic!("Merchant is not approved");
+            return Err(EscrowError::EmergencyCooldownActive);
+        }
+        if assets.is_empty() {
+            return Err(EscrowError::NothingToDrain);
+        }
+
+        let treasury: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencyTreasury)
+            .unwrap();
+        let contract_address = env.current_contract_address();
+        let mut drained = Self::drained_assets(&env);
+        let mut amounts = Vec::new(&env);
+        let mut total: i128 = 0;
+        for asset in assets.iter() {
+            let token_address = Self::token_address(&env, &asset);
+            if drained.iter().any(|record| record.token == token_address) {
+                return Err(EscrowError::NothingToDrain);
             }
+            let token_client = token::Client::new(&env, &token_address);
+            let amount = token_client.balance(&contract_address);
+            amounts.push_back(amount);
+            if amount <= 0 {
+                continue;
+            }
+
+            token_client.transfer(&contract_address, &treasury, &amount);
+            total = total.saturating_add(amount);
+            drained.push_back(DrainRecord {
+                token: token_address.clone(),
+                amount,
+                ledger: current_ledger,
+            });
+            env.events().publish(
+                ("ESCROW", "emergency_drain"),
+                EmergencyDrainEvent {
+                    token: token_address,
+                    amount,
+                    caller: caller.clone(),
+                },
+            );
+        }
+        if total == 0 {
+            return Err(EscrowError::NothingToDrain);
         }
 
-        // Transfer funds into the contract via the appropriate token interface.
-        let token_addr = Self::token_address(&env, &asset_type);
-        token::Client::new(&env, &token_addr).transfer(
-            &customer,
+        env.storage()
+            .instance()
+            .set(&DataKey::DrainedAssets, &drained);
+        env.storage()
+            .instance()
+            .set(&DataKey::EmergencyLastDrainLedger, &current_ledger);
+
+        Ok(amounts)
+    }
+
+    /// Return a drained asset from the treasury and unfreeze its escrows.
+    ///
+    /// Requires the treasury's authorization; the full drained amount is
+    /// transferred back in the same call.  Deposits resume once no asset is
+    /// left drained.
+    pub fn restore_asset(env: Env, asset: AssetType) -> Result<(), EscrowError> {
+        let treasury: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencyTreasury)
+            .unwrap();
+        treasury.require_auth();
+
+        let token_address = Self::token_address(&env, &asset);
+        let mut drained = Self::drained_assets(&env);
+        let index = match drained
+            .iter()
+            .position(|record| record.token == token_address)
+        {
+            Some(index) => index as u32,
+            None => return Err(EscrowError::AssetNotDrained),
+        };
+        let record = drained.get(index).unwrap();
+
+        token::Client::new(&env, &token_address).transfer(
+            &treasury,
             &env.current_contract_address(),
-            &amount,
+            &record.amount,
+        );
+        drained.remove(index);
+        env.storage()
+            .instance()
+            .set(&DataKey::DrainedAssets, &drained);
+
+        env.events().publish(
+            ("ESCROW", "asset_restored"),
+            AssetRestoredEvent {
+                token: token_address,
+                amount: record.amount,
+            },
         );
+        Ok(())
+    }
 
-        let expiry = env.ledger().sequence().saturating_add(ttl_ledgers);
-        let dispute_window_end = {
-            let max_window_end = env
-                .ledger()
-                .sequence()
-                .saturating_add(MAX_DISPUTE_WINDOW_LEDGERS);
-            if expiry < max_window_end {
-                expiry
-            } else {
-                max_window_end
+    /// Replace the emergency signer set and threshold.  `signers` must reach
+    /// the current threshold of the current set.
+    pub fn rotate_emergency_signers(
+        env: Env,
+        caller: Address,
+        signers: Vec<Address>,
+        new_signers: Vec<Address>,
+        new_threshold: u32,
+    ) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_emergency_quorum(&env, &caller, &signers)?;
+        Self::validate_signer_set(&new_signers, new_threshold)?;
+
+        env.storage()
+            .instance()
+            .set(&DataKey::EmergencySigners, &new_signers);
+        env.storage()
+            .instance()
+            .set(&DataKey::EmergencyThreshold, &new_threshold);
+
+        env.events().publish(
+            ("ESCROW", "signers_rotated"),
+            EmergencySignersEvent {
+                signers: new_signers,
+                threshold: new_threshold,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get_emergency_signers(env: Env) -> Result<Vec<Address>, EscrowError> {
+        Ok(env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencySigners)
+            .unwrap())
+    }
+
+    /// Contracts deployed before the threshold was configurable use 2.
+    pub fn get_emergency_threshold(env: Env) -> Result<u32, EscrowError> {
+        Ok(env
+            .storage()
+            .instance()
+            .get(&DataKey::EmergencyThreshold)
+            .unwrap_or(MIN_EMERGENCY_THRESHOLD))
+    }
+
+    /// Raise a pause flag.  Callable by the admin or any emergency signer so
+    /// an incident can be contained without waiting on the admin.
+    pub fn pause(env: Env, caller: Address, scope: PauseScope) -> Result<(), EscrowError> {
+        caller.require_auth();
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        if caller != admin {
+            Self::require_emergency_signer(&env, &caller)?;
+        }
+
+        let key = DataKey::Paused(scope.clone());
+        if env.storage().instance().has(&key) {
+            return Err(EscrowError::AlreadyPaused);
+        }
+        env.storage().instance().set(&key, &true);
+
+        env.events()
+            .publish(("ESCROW", "paused"), PauseEvent { scope, caller });
+        Ok(())
+    }
+
+    /// Clear a pause flag.  Admin-only.
+    pub fn unpause(env: Env, caller: Address, scope: PauseScope) -> Result<(), EscrowError> {
+        caller.require_auth();
+        Self::require_admin(&env, &caller)?;
+
+        let key = DataKey::Paused(scope.clone());
+        if !env.storage().instance().has(&key) {
+            return Err(EscrowError::NotPaused);
+        }
+        env.storage().instance().remove(&key);
+
+        env.events()
+            .publish(("ESCROW", "unpaused"), PauseEvent { scope, caller });
+        Ok(())
+    }
+
+    pub fn is_paused(env: Env, scope: PauseScope) -> Result<bool, EscrowError> {
+        Ok(env.storage().instance().has(&DataKey::Paused(scope)))
+    }
+
+    /// Assets currently held by the treasury after an emergency drain.
+    pub fn get_drained_assets(env: Env) -> Result<Vec<DrainRecord>, EscrowError> {
+        Ok(Self::drained_assets(&env))
+    }
+
+    fn drained_assets(env: &Env) -> Vec<DrainRecord> {
+        env.storage()
+            .instance()
+            .get(&DataKey::DrainedAssets)
+            .unwrap_or(Vec::new(env))
+    }
+
+    fn require_releases_open(env: &Env) -> Result<(), EscrowError> {
+        if Self::is_paused(env.clone(), PauseScope::Releases)? {
+            return Err(EscrowError::ReleasesPaused);
+        }
+        Ok(())
+    }
+
+    fn require_admin(env: &Env, caller: &Address) -> Result<(), EscrowError> {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        if caller != &admin {
+            return Err(EscrowError::NotAdmin);
+        }
+        Ok(())
+    }
+
+    fn require_role(env: &Env, caller: &Address, role: Role) -> Result<(), EscrowError> {
+        let holder: Option<Address> = env.storage().instance().get(&DataKey::Role(role));
+        match holder {
+            None => Self::require_admin(env, caller),
+            Some(holder) if holder == *caller => Ok(()),
+            Some(_) => Err(EscrowError::MissingRole),
+        }
+    }
+
+    /// Dispute resolution is limited to the arbiter set.  Deployments that have
+    /// not configured any arbiters fall back to the admin.
+    fn require_arbiter(env: &Env, caller: &Address) -> Result<(), EscrowError> {
+        let arbiters = Self::get_arbiters(env.clone())?;
+        if arbiters.is_empty() {
+            Self::require_admin(env, caller)?;
+        } else if !arbiters.contains(caller) {
+            return Err(EscrowError::MissingRole);
+        }
+        Ok(())
+    }
+
+    /// Read a payment, upgrading records written before the `token` field
+    /// existed and reporting open payments in a drained asset as `Frozen`.
+    fn load_payment(env: &Env, payment_id: BytesN<32>) -> Option<PaymentEscrow> {
+        let mut raw: Map<Symbol, Val> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Payment(payment_id))?;
+        let mut payment = if raw.contains_key(symbol_short!("token")) {
+            // Entries written by earlier versions lack the optional fields
+            // added since; treat them as unset.
+            for field in [
+                symbol_short!("refund_to"),
+                Symbol::new(env, "auto_release_after"),
+                Symbol::new(env, "invoice_id"),
+                Symbol::new(env, "stream_start"),
+                Symbol::new(env, "stream_end"),
+                symbol_short!("hashlock"),
+                symbol_short!("attester"),
+                Symbol::new(env, "condition_hash"),
+            ] {
+                if !raw.contains_key(field.clone()) {
+                    raw.set(field, Val::VOID.into());
+                }
             }
+            PaymentEscrow::try_from_val(env, raw.as_val()).unwrap()
+        } else {
+            Self::upgrade_legacy_payment(env, raw)
         };
 
-        let payment = PaymentEscrow {
-            payment_id: payment_id.clone(),
-            amount,
-            released_amount: 0,
-            merchant: merchant.clone(),
-            customer: customer.clone(),
-            status: PaymentStatus::Pending,
-            expiry,
-            dispute_window_end,
-            dispute_reason: None,
-            asset_type,
+        if matches!(payment.status, PaymentStatus::Pending | PaymentStatus::Disputed)
+            && Self::drained_assets(env)
+                .iter()
+                .any(|record| record.token == payment.token)
+        {
+            payment.status = PaymentStatus::Frozen;
+        }
+        Some(payment)
+    }
+
+    fn upgrade_legacy_payment(env: &Env, raw: Map<Symbol, Val>) -> PaymentEscrow {
+        let legacy = LegacyPaymentEscrow::try_from_val(env, raw.as_val()).unwrap();
+        let token = Self::token_address(env, &legacy.asset_type);
+        PaymentEscrow {
+            payment_id: legacy.payment_id,
+            amount: legacy.amount,
+            released_amount: legacy.released_amount,
+            merchant: legacy.merchant,
+            customer: legacy.customer,
+            status: legacy.status,
+            expiry: legacy.expiry,
+            dispute_window_end: legacy.dispute_window_end,
+            dispute_reason: legacy.dispute_reason,
+            asset_type: legacy.asset_type,
+            token,
+            refund_to: None,
+            auto_release_after: None,
+            invoice_id: None,
+            stream_start: None,
+            stream_end: None,
+            hashlock: None,
+            attester: None,
+            condition_hash: None,
+        }
+    }
+
+    /// Mark the payment request an escrow paid as `Failed` once the escrow
+    /// has ended refunded.  Best effort: a request the backend already settled
+    /// or expired, or an unset request contract, never blocks the refund.
+    fn fail_invoice(env: &Env, payment: &PaymentEscrow) {
+        if payment.status != PaymentStatus::Expired {
+            return;
+        }
+        let (Some(invoice_id), Some(requests)) = (
+            payment.invoice_id.as_ref(),
+            env.storage()
+                .instance()
+                .get::<_, Address>(&DataKey::InvoiceContract),
+        ) else {
+            return;
         };
+        let _ = PaymentRequestClient::new(env, &requests).try_fail(invoice_id);
+    }
+
+    fn save_payment(env: &Env, payment: &PaymentEscrow) {
+        env.storage()
+            .persistent()
+            .set(&DataKey::Payment(payment.payment_id.clone()), payment);
+        Self::extend_payment_ttl(env, payment);
+    }
+
+    fn extend_payment_ttl(env: &Env, payment: &PaymentEscrow) {
+        let payment_id = &payment.payment_id;
+        let mut keys = Vec::from_array(
+            env,
+            [
+                DataKey::Payment(payment_id.clone()),
+                DataKey::Split(payment_id.clone()),
+                DataKey::Milestones(payment_id.clone()),
+            ],
+        );
+        // Dispute entries only exist once a dispute was opened; skipping them
+        // otherwise keeps batch calls inside the footprint limit.
+        let mut deadline = 0;
+        if payment.dispute_reason.is_some() {
+            deadline = env
+                .storage()
+                .persistent()
+                .get(&DataKey::DisputeDeadline(payment_id.clone()))
+                .unwrap_or(0);
+            keys.push_back(DataKey::Evidence(payment_id.clone()));
+            keys.push_back(DataKey::DisputeDeadline(payment_id.clone()));
+        }
+
+        let extend_to = payment
+            .expiry
+            .max(deadline)
+            .saturating_sub(env.ledger().sequence())
+            .saturating_add(PAYMENT_TTL_GRACE_LEDGERS)
+            .min(env.storage().max_ttl());
+        for key in keys.iter() {
+            if env.storage().persistent().has(&key) {
+                env.storage().persistent().extend_ttl(&key, extend_to, extend_to);
+            }
+        }
+        Self::extend_instance_ttl(env);
+    }
+
+    /// Append a new escrow to `index`, keeping the index alive at least as
+    /// long as the escrow.
+    fn append_to_index(env: &Env, index: EscrowIndex, payment: &PaymentEscrow) {
+        let len_key = DataKey::IndexLen(index.clone());
+        let len: u32 = env.storage().persistent().get(&len_key).unwrap_or(0);
+        let page_key = DataKey::IndexPage(index, len / INDEX_PAGE_SIZE);
+        let mut page: Vec<BytesN<32>> = env
+            .storage()
+            .persistent()
+            .get(&page_key)
+            .unwrap_or(Vec::new(env));
+        page.push_back(payment.payment_id.clone());
+        env.storage().persistent().set(&page_key, &page);
+        env.storage().persistent().set(&len_key, &(len + 1));
+
+        let extend_to = payment
+            .expiry
+            .saturating_sub(env.ledger().sequence())
+            .saturating_add(PAYMENT_TTL_GRACE_LEDGERS)
+            .min(env.storage().max_ttl());
+        for key in [len_key, page_key] {
+            env.storage().persistent().extend_ttl(&key, extend_to, extend_to);
+        }
+    }
 
-        env.storage().persistent().set(&key, &payment);
+    fn read_index(
+        env: &Env,
+        index: EscrowIndex,
+        start: u32,
+        limit: u32,
+        keep: impl Fn(&PaymentEscrow) -> bool,
+    ) -> EscrowPage {
+        let len = Self::get_index_len(env.clone(), index.clone()).unwrap_or(0);
+        let end = start.saturating_add(limit.min(MAX_INDEX_QUERY_SIZE)).min(len);
+
+        let mut payments = Vec::new(env);
+        let mut page: Vec<BytesN<32>> = Vec::new(env);
+        for position in start..end {
+            if position == start || position % INDEX_PAGE_SIZE == 0 {
+                page = env
+                    .storage()
+                    .persistent()
+                    .get(&DataKey::IndexPage(index.clone(), position / INDEX_PAGE_SIZE))
+                    .unwrap_or(Vec::new(env));
+            }
+            let payment_id = page.get(position % INDEX_PAGE_SIZE).unwrap();
+            if let Some(payment) = Self::load_payment(env, payment_id) {
+                if keep(&payment) {
+                    payments.push_back(payment);
+                }
+            }
+        }
 
-        env.events().publish(
-            ("ESCROW", "deposit"),
-            DepositEvent {
-                payment_id: payment_id.clone(),
-                customer,
-                merchant,
-                amount,
-                expiry,
-            },
-        );
+        EscrowPage {
+            payments,
+            next: if end < len { Some(end) } else { None },
+        }
+    }
 
-        payment_id
+    fn extend_instance_ttl(env: &Env) {
+        env.storage()
+            .instance()
+            .extend_ttl(CONFIG_TTL_THRESHOLD, CONFIG_TTL_EXTEND_TO);
     }
 
-    pub fn release(env: Env, caller: Address, payment_id: BytesN<32>) {
-        caller.require_auth();
-        Self::require_admin(&env, &caller);
+    fn release_payment(env: &Env, payment_id: BytesN<32>) -> Result<i128, EscrowError> {
+        let payment = Self::get_payment(env.clone(), payment_id)?;
+        Self::require_lump_sum(&payment)?;
+        Self::release_remaining(env, payment)
+    }
 
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        Self::require_releasable(&env, &payment);
+    /// Pay the merchant everything left in `payment` and mark it released.
+    fn release_remaining(env: &Env, mut payment: PaymentEscrow) -> Result<i128, EscrowError> {
+        Self::require_releasable(env, &payment)?;
+        Self::require_no_milestones(env, &payment.payment_id)?;
 
         let remaining = Self::remaining_amount(&payment);
         if remaining <= 0 {
-            panic!("Payment fully released");
+            return Err(EscrowError::PaymentFullyReleased);
         }
 
-        Self::transfer_from_contract(&env, &payment.merchant, remaining, &payment.asset_type);
+        let prev_status = payment.status.clone();
+        Self::pay_merchant(env, &payment, remaining)?;
         payment.released_amount = payment.amount;
         payment.status = PaymentStatus::Released;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
-
-        env.events().publish(
-            ("ESCROW", "release"),
-            EscrowStateEvent {
-                payment_id,
-                amount: remaining,
-            },
+        Self::save_payment(env, &payment);
+
+        Self::publish_transition(
+            env,
+            "release",
+            &payment,
+            prev_status,
+            Some(payment.merchant.clone()),
+            remaining,
+            None,
         );
+        Ok(remaining)
     }
 
-    pub fn release_partial(env: Env, caller: Address, payment_id: BytesN<32>, amount: i128) {
-        caller.require_auth();
-        Self::require_admin(&env, &caller);
+    /// Return the remaining balance to the customer of an expired payment,
+    /// publishing under `topic`.  The vested part of a stream, and milestones
+    /// past their deadline under a `Release` default, go to the merchant.
+    fn refund_payment(
+        env: &Env,
+        payment_id: BytesN<32>,
+        topic: &'static str,
+    ) -> Result<i128, EscrowError> {
+        let mut payment = Self::get_payment(env.clone(), payment_id.clone())?;
+        Self::require_expirable(env, &payment)?;
 
-        if amount <= 0 {
-            panic!("Release amount must be > 0");
+        let remaining = Self::remaining_amount(&payment);
+        if remaining <= 0 {
+            return Err(EscrowError::PaymentFullyReleased);
         }
 
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        Self::require_releasable(&env, &payment);
+        // A stream ends before its escrow expires, so by now everything it
+        // still holds has vested and belongs to the merchant.  Milestones get
+        // their deadline default, so expiring cannot pre-empt `settle_milestone`.
+        let (owed, payout_topic) = if payment.stream_start.is_some() {
+            (Self::withdrawable_amount(env, &payment)?, "withdraw")
+        } else {
+            (Self::release_due_milestones(env, &payment_id), "milestone_released")
+        };
+        let refunded = remaining - owed;
 
-        let remaining = Self::remaining_amount(&payment);
-        if remaining <= 0 {
-            panic!("Payment fully released");
+        let prev_status = payment.status.clone();
+        if owed > 0 {
+            Self::pay_merchant(env, &payment, owed)?;
         }
-        if amount > remaining {
-            panic!("Release amount exceeds remaining balance");
+        let refund_to = Self::refund_address(&payment);
+        if refunded > 0 {
+            Self::transfer_from_contract(env, &refund_to, refunded, &payment.token);
         }
-
-        Self::transfer_from_contract(&env, &payment.merchant, amount, &payment.asset_type);
-        payment.released_amount += amount;
-        if payment.released_amount == payment.amount {
-            payment.status = PaymentStatus::Released;
+        Self::close_open_milestones(env, &payment_id, MilestoneStatus::Refunded);
+        payment.released_amount = payment.amount;
+        payment.status = if refunded > 0 {
+            PaymentStatus::Expired
+        } else {
+            PaymentStatus::Released
+        };
+        Self::save_payment(env, &payment);
+        Self::fail_invoice(env, &payment);
+
+        if owed > 0 {
+            Self::publish_transition(
+                env,
+                payout_topic,
+                &payment,
+                prev_status.clone(),
+                Some(payment.merchant.clone()),
+                owed,
+                None,
+            );
         }
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
+        Self::publish_transition(
+            env,
+            topic,
+            &payment,
+            prev_status,
+            Some(refund_to).filter(|_| refunded > 0),
+            refunded,
+            None,
+        );
+        Ok(remaining)
+    }
 
+    /// Publish an `EscrowEvent` for `payment`, which is already in its new
+    /// state.  `recipient` is who was paid `amount`, if funds left the escrow.
+    fn publish_transition(
+        env: &Env,
+        topic: &'static str,
+        payment: &PaymentEscrow,
+        prev_status: PaymentStatus,
+        recipient: Option<Address>,
+        amount: i128,
+        milestone: Option<u32>,
+    ) {
         env.events().publish(
-            ("ESCROW", "release"),
-            EscrowStateEvent {
-                payment_id,
+            ("ESCROW", topic),
+            EscrowEvent {
+                version: EVENT_SCHEMA_VERSION,
+                payment_id: payment.payment_id.clone(),
+                asset: payment.token.clone(),
+                customer: payment.customer.clone(),
+                merchant: payment.merchant.clone(),
+                recipient,
                 amount,
+                prev_status,
+                status: payment.status.clone(),
+                released_amount: payment.released_amount,
+                expiry: payment.expiry,
+                dispute_reason: payment.dispute_reason.clone(),
+                milestone,
             },
         );
     }
 
-    pub fn expire(env: Env, payment_id: BytesN<32>) {
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        Self::require_expirable(&env, &payment);
+    fn publish_dispute_resolved(
+        env: &Env,
+        payment: &PaymentEscrow,
+        prev_status: PaymentStatus,
+        refund_to: Address,
+        merchant_amount: i128,
+        customer_amount: i128,
+    ) {
+        env.events().publish(
+            ("ESCROW", "dispute_resolved"),
+            DisputeResolvedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                payment_id: payment.payment_id.clone(),
+                asset: payment.token.clone(),
+                customer: payment.customer.clone(),
+                merchant: payment.merchant.clone(),
+                refund_to,
+                merchant_amount,
+                customer_amount,
+                prev_status,
+                status: payment.status.clone(),
+                released_amount: payment.released_amount,
+                dispute_reason: payment.dispute_reason.clone(),
+            },
+        );
+    }
 
-        let remaining = Self::remaining_amount(&payment);
-        if remaining <= 0 {
-            panic!("Payment fully released");
+    fn max_batch_size(env: &Env) -> u32 {
+        let storage = env.storage().instance();
+        match (
+            storage.has(&DataKey::FeeHooks),
+            storage.has(&DataKey::SettlementLedger),
+            storage.has(&DataKey::RegistryContract),
+        ) {
+            (true, true, true) => MAX_FULL_BATCH_SIZE,
+            (true, _, _) => MAX_FEE_BATCH_SIZE,
+            (false, true, true) => MAX_KYC_SETTLED_BATCH_SIZE,
+            (false, true, false) => MAX_SETTLED_BATCH_SIZE,
+            (false, false, true) => MAX_KYC_BATCH_SIZE,
+            (false, false, false) => MAX_BATCH_SIZE,
         }
+    }
 
-        Self::transfer_from_contract(&env, &payment.customer, remaining);
-        payment.released_amount = payment.amount;
-        payment.status = PaymentStatus::Expired;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
+    fn run_batch(
+        env: &Env,
+        payment_ids: Vec<BytesN<32>>,
+        op: impl Fn(&Env, BytesN<32>) -> Result<i128, EscrowError>,
+    ) -> Result<Vec<BatchItemResult>, EscrowError> {
+        if payment_ids.len() > Self::max_batch_size(env) {
+            return Err(EscrowError::LimitExceeded);
+        }
 
-        env.events().publish(
-            ("ESCROW", "expired"),
-            EscrowStateEvent {
+        let mut results = Vec::new(env);
+        for payment_id in payment_ids.iter() {
+            let (amount, error) = match op(env, payment_id.clone()) {
+                Ok(amount) => (amount, None),
+                Err(error) => (0, Some(error as u32)),
+            };
+            results.push_back(BatchItemResult {
                 payment_id,
-                amount: remaining,
-            },
-        );
+                amount,
+                error,
+            });
+        }
+        Ok(results)
     }
 
-    pub fn refund(env: Env, payment_id: BytesN<32>) {
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        Self::require_expirable(&env, &payment);
+    /// If a registry contract is configured, verify the merchant has passed KYC
+    /// before any funds are paid out to them.
+    fn require_kyc_verified(env: &Env, merchant: &Address) -> Result<(), EscrowError> {
+        if let Some(registry_addr) = env
+            .storage()
+            .instance()
+            .get::<DataKey, Address>(&DataKey::RegistryContract)
+        {
+            let registry_client = MerchantRegistryClient::new(env, &registry_addr);
+            if !registry_client.is_kyc_verified(merchant) {
+                return Err(EscrowError::MerchantNotKycVerified);
+            }
+        }
+        Ok(())
+    }
 
-        let remaining = Self::remaining_amount(&payment);
-        if remaining <= 0 {
-            panic!("Payment fully released");
+    fn require_releasable(env: &Env, payment: &PaymentEscrow) -> Result<(), EscrowError> {
+        match payment.status {
+            PaymentStatus::Disputed => Err(EscrowError::DisputeOpen),
+            PaymentStatus::Released => Err(EscrowError::PaymentFullyReleased),
+            PaymentStatus::Expired => Err(EscrowError::PaymentExpired),
+            PaymentStatus::Frozen => Err(EscrowError::PaymentFrozen),
+            PaymentStatus::Pending if env.ledger().sequence() > payment.expiry => {
+                Err(EscrowError::PaymentExpired)
+            }
+            PaymentStatus::Pending => Ok(()),
+            _ => Err(EscrowError::PaymentNotPending),
         }
+    }
 
-        Self::transfer_from_contract(&env, &payment.customer, remaining, &payment.asset_type);
-        payment.released_amount = payment.amount;
-        payment.status = PaymentStatus::Expired;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
+    fn require_expirable(env: &Env, payment: &PaymentEscrow) -> Result<(), EscrowError> {
+        match payment.status {
+            PaymentStatus::Disputed => Err(EscrowError::DisputeOpen),
+            PaymentStatus::Released => Err(EscrowError::PaymentFullyReleased),
+            PaymentStatus::Frozen => Err(EscrowError::PaymentFrozen),
+            PaymentStatus::Pending if env.ledger().sequence() <= payment.expiry => {
+                Err(EscrowError::PaymentNotExpired)
+            }
+            PaymentStatus::Pending => Ok(()),
+            _ => Err(EscrowError::PaymentNotPending),
+        }
+    }
 
-        env.events().publish(
-            ("ESCROW", "refund"),
-            EscrowStateEvent {
-                payment_id,
-                amount: remaining,
-            },
-        );
+    fn remaining_amount(payment: &PaymentEscrow) -> i128 {
+        payment.amount.saturating_sub(payment.released_amount)
     }
 
-    pub fn dispute(env: Env, caller: Address, payment_id: BytesN<32>, _reason: String) {
-        caller.require_auth();
+    fn refund_address(payment: &PaymentEscrow) -> Address {
+        payment.refund_to.clone().unwrap_or(payment.customer.clone())
+    }
 
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        if payment.status == PaymentStatus::Disputed {
-            panic!("Dispute already open");
+    fn token_address(env: &Env, asset_type: &AssetType) -> Address {
+        match asset_type {
+            AssetType::Xlm => env.storage().instance().get(&DataKey::XlmToken).unwrap(),
+            AssetType::Usdc => env.storage().instance().get(&DataKey::UsdcToken).unwrap(),
+            AssetType::Token(token) => token.clone(),
         }
-        if payment.status != PaymentStatus::Pending {
-            panic!("Payment is not pending");
+    }
+
+    /// XLM and USDC are always accepted; any other token must be allowlisted.
+    /// When limits are configured for the token, `amount` must fall within them.
+    fn require_within_token_limits(
+        env: &Env,
+        asset_type: &AssetType,
+        token: &Address,
+        amount: i128,
+    ) -> Result<(), EscrowError> {
+        let limits: Option<TokenLimits> = env
+            .storage()
+            .instance()
+            .get(&DataKey::AllowedToken(token.clone()));
+        match limits {
+            Some(limits) => {
+                if amount < limits.min_amount || amount > limits.max_amount {
+                    return Err(EscrowError::AmountOutOfRange);
+                }
+            }
+            None => {
+                if let AssetType::Token(_) = asset_type {
+                    return Err(EscrowError::TokenNotAllowed);
+                }
+            }
         }
-        if caller != payment.customer && caller != payment.merchant {
-            panic!("Not payment participant");
+        Ok(())
+    }
+
+    /// Validate split inputs and resolve every share to an absolute amount.
+    fn resolve_split(
+        env: &Env,
+        amount: i128,
+        recipients: &Vec<SplitRecipientInput>,
+    ) -> Result<Vec<SplitRecipient>, EscrowError> {
+        if recipients.is_empty() {
+            return Err(EscrowError::InvalidSplit);
         }
-        if env.ledger().sequence() > payment.dispute_window_end {
-            panic!("Dispute window expired");
+        if recipients.len() > MAX_SPLIT_RECIPIENTS {
+            return Err(EscrowError::LimitExceeded);
         }
 
-        payment.status = PaymentStatus::Disputed;
-        payment.dispute_reason = Some(_reason.clone());
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
-
-        env.events().publish(
-            ("ESCROW", "dispute"),
-            EscrowStateEvent {
-                payment_id,
-                amount: Self::remaining_amount(&payment),
-            },
-        );
-    }
+        let mut fixed_total: i128 = 0;
+        let mut bps_total: i128 = 0;
+        for (i, input) in recipients.iter().enumerate() {
+            for other in recipients.iter().skip(i + 1) {
+                if other.recipient == input.recipient {
+                    return Err(EscrowError::InvalidSplit);
+                }
+            }
+            match input.share {
+                SplitShare::Fixed(value) => {
+                    if value <= 0 {
+                        return Err(EscrowError::InvalidAmount);
+                    }
+                    fixed_total = fixed_total.saturating_add(value);
+                }
+                SplitShare::Bps(bps) => {
+                    if bps == 0 {
+                        return Err(EscrowError::InvalidAmount);
+                    }
+                    bps_total += bps as i128;
+                }
+            }
+        }
 
-    pub fn resolve_dispute(env: Env, caller: Address, payment_id: BytesN<32>, winner: Address) {
-        caller.require_auth();
-        Self::require_admin(&env, &caller);
+        if bps_total == 0 {
+            if fixed_total != amount {
+                return Err(EscrowError::InvalidSplit);
+            }
+        } else if bps_total != BPS_DENOMINATOR || fixed_total >= amount {
+            return Err(EscrowError::InvalidSplit);
+        }
 
-        let mut payment = Self::get_payment(env.clone(), payment_id.clone());
-        if payment.status != PaymentStatus::Disputed {
-            panic!("Dispute is not open");
+        // Basis-point shares divide the remainder after fixed amounts; rounding
+        // dust goes to the first basis-point recipient.
+        let bps_pool = amount - fixed_total;
+        let mut split: Vec<SplitRecipient> = Vec::new(env);
+        let mut allocated: i128 = 0;
+        for input in recipients.iter() {
+            let share_amount = match input.share {
+                SplitShare::Fixed(value) => value,
+                SplitShare::Bps(bps) => bps_pool * (bps as i128) / BPS_DENOMINATOR,
+            };
+            allocated += share_amount;
+            split.push_back(SplitRecipient {
+                recipient: input.recipient,
+                amount: share_amount,
+                released: 0,
+            });
         }
-        if winner != payment.customer && winner != payment.merchant {
-            panic!("Invalid dispute winner");
+        let dust = amount - allocated;
+        if dust > 0 {
+            let index = recipients
+                .iter()
+                .position(|input| matches!(input.share, SplitShare::Bps(_)))
+                .unwrap() as u32;
+            let mut first = split.get(index).unwrap();
+            first.amount += dust;
+            split.set(index, first);
         }
 
-        let remaining = Self::remaining_amount(&payment);
-        if remaining <= 0 {
-            panic!("Payment fully released");
+        if split.iter().any(|entry| entry.amount <= 0) {
+            return Err(EscrowError::InvalidSplit);
         }
+        Ok(split)
+    }
 
-        Self::transfer_from_contract(&env, &winner, remaining, &payment.asset_type);
-        payment.status = if winner == payment.merchant {
-            PaymentStatus::Released
-        } else {
-            PaymentStatus::Expired
+    /// Pay `amount` of the escrow's merchant side, less any fee: directly to
+    /// the merchant, or pro-rata across the split recipients when the escrow
+    /// has a split.  Fails while releases are paused or if the merchant is not
+    /// KYC verified, so no payout path can skip either gate.
+    ///
+    /// Every payout is recorded in the settlement ledger, keyed by the
+    /// escrow's `released_amount` before it, so callers must update that only
+    /// after paying.
+    fn pay_merchant(env: &Env, payment: &PaymentEscrow, amount: i128) -> Result<(), EscrowError> {
+        Self::require_releases_open(env)?;
+        Self::require_kyc_verified(env, &payment.merchant)?;
+
+        let fee = Self::charge_fee(env, payment, amount)?;
+        let key = DataKey::Split(payment.payment_id.clone());
+        let mut split: Vec<SplitRecipient> = match env.storage().persistent().get(&key) {
+            Some(split) => split,
+            None => {
+                if amount > fee {
+                    Self::transfer_from_contract(
+                        env,
+                        &payment.merchant,
+                        amount - fee,
+                        &payment.token,
+                    );
+                }
+                Self::record_settlement(env, payment, amount, fee);
+                return Ok(());
+            }
         };
-        payment.released_amount = payment.amount;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Payment(payment_id.clone()), &payment);
 
-        let topic = if winner == payment.merchant {
-            "release"
-        } else {
-            "refund"
-        };
-        env.events().publish(
-            ("ESCROW", topic),
-            EscrowStateEvent {
-                payment_id,
-                amount: remaining,
-            },
-        );
-    }
+        // Each recipient first gets its floor share of `amount`, capped at what
+        // it is still owed; any remainder is handed out in recipient order.
+        let mut payouts: Vec<i128> = Vec::new(env);
+        let mut assigned: i128 = 0;
+        for entry in split.iter() {
+            let owed = entry.amount - entry.released;
+            let share = (amount * entry.amount / payment.amount).min(owed);
+            payouts.push_back(share);
+            assigned += share;
+        }
+        let mut leftover = amount - assigned;
+        for i in 0..split.len() {
+            if leftover == 0 {
+                break;
+            }
+            let entry = split.get(i).unwrap();
+            let payout = payouts.get(i).unwrap();
+            let extra = (entry.amount - entry.released - payout).min(leftover);
+            payouts.set(i, payout + extra);
+            leftover -= extra;
+        }
 
-    pub fn get_payment(env: Env, payment_id: BytesN<32>) -> PaymentEscrow {
-        env.storage()
-            .persistent()
-            .get(&DataKey::Payment(payment_id))
-            .expect("Payment not found")
+        // Recipients bear the fee pro rata to their gross payout; the rounding
+        // remainder comes out of the last paid recipient's share.
+        let mut fee_left = fee;
+        let last_paid = (0..split.len()).rev().find(|i| payouts.get(*i).unwrap() > 0);
+        for i in 0..split.len() {
+            let mut entry = split.get(i).unwrap();
+            let payout = payouts.get(i).unwrap();
+            if payout == 0 {
+                continue;
+            }
+            let share_fee = if Some(i) == last_paid {
+                fee_left
+            } else {
+                fee * payout / amount
+            };
+            fee_left -= share_fee;
+            if payout > share_fee {
+                Self::transfer_from_contract(
+                    env,
+                    &entry.recipient,
+                    payout - share_fee,
+                    &payment.token,
+                );
+            }
+            entry.released += payout;
+            split.set(i, entry.clone());
+
+            env.events().publish(
+                ("ESCROW", "split_payout"),
+                SplitPayoutEvent {
+                    payment_id: payment.payment_id.clone(),
+                    recipient: entry.recipient,
+                    amount: payout - share_fee,
+                },
+            );
+        }
+        env.storage().persistent().set(&key, &split);
+        Self::record_settlement(env, payment, amount, fee);
+        Ok(())
     }
 
-    pub fn get_balance(env: Env, payment_id: BytesN<32>) -> i128 {
-        let payment = Self::get_payment(env, payment_id);
-        Self::remaining_amount(&payment)
+    /// Record a merchant release of `amount`, of which `fee` was withheld,
+    /// in the settlement ledger if one is configured.
+    fn record_settlement(env: &Env, payment: &PaymentEscrow, amount: i128, fee: i128) {
+        let ledger: Address = match env.storage().instance().get(&DataKey::SettlementLedger) {
+            Some(ledger) => ledger,
+            None => return,
+        };
+        SettlementLedgerClient::new(env, &ledger).record_settlement(
+            &env.current_contract_address(),
+            &Self::settlement_id(env, &payment.payment_id, payment.released_amount),
+            &payment.merchant,
+            &amount,
+            &fee,
+            &(amount - fee),
+            &env.ledger().timestamp(),
+            &String::from_str(env, ""),
+        );
     }
 
-    pub fn get_expiry(env: Env, payment_id: BytesN<32>) -> u32 {
-        Self::get_payment(env, payment_id).expiry
+    /// Record a fee-free release of `amount` like `record_settlement`, but
+    /// publish `settlement_skipped` instead of failing if the ledger rejects
+    /// it.
+    fn try_record_settlement(env: &Env, payment: &PaymentEscrow, amount: i128) {
+        let ledger: Address = match env.storage().instance().get(&DataKey::SettlementLedger) {
+            Some(ledger) => ledger,
+            None => return,
+        };
+        let settlement_id = Self::settlement_id(env, &payment.payment_id, payment.released_amount);
+        let recorded = SettlementLedgerClient::new(env, &ledger).try_record_settlement(
+            &env.current_contract_address(),
+            &settlement_id,
+            &payment.merchant,
+            &amount,
+            &0,
+            &amount,
+            &env.ledger().timestamp(),
+            &String::from_str(env, ""),
+        );
+        if !matches!(recorded, Ok(Ok(()))) {
+            env.events().publish(
+                ("ESCROW", "settlement_skipped"),
+                SettlementSkippedEvent {
+                    payment_id: payment.payment_id.clone(),
+                    settlement_id,
+                    amount,
+                },
+            );
+        }
     }
 
-    pub fn get_admin(env: Env) -> Address {
-        env.storage().instance().get(&DataKey::Admin).unwrap()
+    /// Escrow id for one period of a subscription: a hash of the
+    /// subscription id and period, with its leading bytes replaced by
+    /// `SUBSCRIPTION_ID_TAG`.
+    fn subscription_payment_id(env: &Env, subscription_id: &BytesN<32>, period: u32) -> BytesN<32> {
+        let mut preimage = Bytes::from_array(env, &subscription_id.to_array());
+        preimage.extend_from_array(&period.to_be_bytes());
+        let mut id = env.crypto().sha256(&preimage).to_array();
+        id[..SUBSCRIPTION_ID_TAG.len()].copy_from_slice(&SUBSCRIPTION_ID_TAG);
+        BytesN::from_array(env, &id)
     }
 
-    pub fn get_xlm_token(env: Env) -> Address {
-        env.storage().instance().get(&DataKey::XlmToken).unwrap()
+    fn is_subscription_payment_id(payment_id: &BytesN<32>) -> bool {
+        payment_id.to_array().starts_with(&SUBSCRIPTION_ID_TAG)
     }
 
-    pub fn get_usdc_token(env: Env) -> Address {
-        env.storage().instance().get(&DataKey::UsdcToken).unwrap()
+    fn save_subscription(env: &Env, subscription: &Subscription) {
+        let key = DataKey::Subscription(subscription.subscription_id.clone());
+        env.storage().persistent().set(&key, subscription);
+        let extend_to = subscription
+            .end_ledger
+            .saturating_sub(env.ledger().sequence())
+            .saturating_add(PAYMENT_TTL_GRACE_LEDGERS)
+            .min(env.storage().max_ttl());
+        env.storage().persistent().extend_ttl(&key, extend_to, extend_to);
     }
 
-    pub fn get_default_ttl_ledgers(env: Env) -> u32 {
-        env.storage()
-            .instance()
-            .get(&DataKey::DefaultTtlLedgers)
-            .unwrap()
+    /// Add `delta` (which may be negative) to `owner`'s allowance to this
+    /// contract.  Allowances are shared by all of an owner's subscriptions in
+    /// a token, so they are adjusted rather than overwritten, and kept alive
+    /// for as long as the network allows.
+    fn adjust_allowance(env: &Env, token: &Address, owner: &Address, delta: i128) {
+        let token = token::Client::new(env, token);
+        let escrow = env.current_contract_address();
+        let allowance = token.allowance(owner, &escrow).saturating_add(delta).max(0);
+        // The token keeps the allowance entry alive one ledger past its
+        // expiration, so the latest usable expiration is one below the max.
+        let expiration = env.ledger().max_live_until_ledger() - 1;
+        token.approve(owner, &escrow, &allowance, &expiration);
     }
 
-    pub fn get_max_dispute_window_ledgers(_env: Env) -> u32 {
-        MAX_DISPUTE_WINDOW_LEDGERS
+    fn attestation_payload(
+        env: &Env,
+        payment_id: BytesN<32>,
+        condition_hash: BytesN<32>,
+    ) -> AttestationPayload {
+        AttestationPayload {
+            network_id: env.ledger().network_id(),
+            contract: env.current_contract_address(),
+            payment_id,
+            condition_hash,
+        }
     }
 
-    pub fn get_max_ttl_ledgers(_env: Env) -> u32 {
-        MAX_TTL_LEDGERS
+    fn settlement_id(env: &Env, payment_id: &BytesN<32>, released_before: i128) -> BytesN<32> {
+        if released_before == 0 {
+            return payment_id.clone();
+        }
+        let mut preimage = Bytes::from_array(env, &payment_id.to_array());
+        preimage.extend_from_array(&released_before.to_be_bytes());
+        env.crypto().sha256(&preimage).into()
     }
 
-    pub fn emergency_drain(env: Env, caller: Address, signer_one: Address, signer_two: Address) -> i128 {
-        caller.require_auth();
-        if signer_one == signer_two {
-            panic!("Emergency signers must be distinct");
+    /// Quote the fee on a USDC payout of `amount` and hand it to the
+    /// distributor.  Returns the fee, which the caller withholds from the
+    /// payout; zero when no hooks are set or the escrow is not in USDC.
+    fn charge_fee(env: &Env, payment: &PaymentEscrow, amount: i128) -> Result<i128, EscrowError> {
+        let hooks: FeeHooks = match env.storage().instance().get(&DataKey::FeeHooks) {
+            Some(hooks) => hooks,
+            None => return Ok(0),
+        };
+        let usdc: Address = env.storage().instance().get(&DataKey::UsdcToken).unwrap();
+        if amount <= 0 || payment.token != usdc {
+            return Ok(0);
         }
-        signer_one.require_auth();
-        signer_two.require_auth();
-        Self::require_emergency_signer(&env, &signer_one);
-        Self::require_emergency_signer(&env, &signer_two);
 
-        let current_ledger = env.ledger().sequence();
-        let last_drain_ledger: u32 = env
-            .storage()
-            .instance()
-            .get(&DataKey::EmergencyLastDrainLedger)
-            .unwrap_or(0);
-        let cooldown_ledgers: u32 = env
-            .storage()
-            .instance()
-            .get(&DataKey::EmergencyCooldownLedgers)
-            .unwrap();
-        if last_drain_ledger != 0
-            && current_ledger < last_drain_ledger.saturating_add(cooldown_ledgers)
-        {
-            panic!("Emergency drain cooldown active");
+        let calculator = FeeCalculatorClient::new(env, &hooks.calculator);
+        let (fee, _, fee_bps) = calculator.calculate_fee(&payment.merchant, &amount);
+        if fee < 0 || fee > amount {
+            return Err(EscrowError::InvalidConfig);
         }
-
-        let usdc_token: Address = env.storage().instance().get(&DataKey::UsdcToken).unwrap();
-        let treasury: Address = env
-            .storage()
-            .instance()
-            .get(&DataKey::EmergencyTreasury)
-            .unwrap();
-        let token_client = token::Client::new(&env, &usdc_token);
-        let contract_address = env.current_contract_address();
-        let amount = token_client.balance(&contract_address);
-        if amount <= 0 {
-            panic!("No escrow funds to drain");
+        if fee == 0 {
+            return Ok(0);
         }
 
-        token_client.transfer(&contract_address, &treasury, &amount);
-        env.storage()
-            .instance()
-            .set(&DataKey::EmergencyLastDrainLedger, &current_ledger);
+        // The distributor pulls the fee itself, so authorize that one transfer.
+        let escrow = env.current_contract_address();
+        env.authorize_as_current_contract(vec![
+            env,
+            InvokerContractAuthEntry::Contract(SubContractInvocation {
+                context: ContractContext {
+                    contract: usdc,
+                    fn_name: symbol_short!("transfer"),
+                    args: (escrow.clone(), hooks.distributor.clone(), fee).into_val(env),
+                },
+                sub_invocations: Vec::new(env),
+            }),
+        ]);
+        FeeDistributorClient::new(env, &hooks.distributor).distribute(&escrow, &fee);
+
         env.events().publish(
-            ("ESCROW", "emergency_drain"),
-            EmergencyDrainEvent { amount, caller },
+            ("ESCROW", "fee_charged"),
+            FeeChargedEvent {
+                payment_id: payment.payment_id.clone(),
+                amount,
+                fee,
+                fee_bps,
+            },
         );
+        Ok(fee)
+    }
 
-        amount
+    fn get_milestone_plan(
+        env: &Env,
+        payment_id: &BytesN<32>,
+    ) -> Result<MilestonePlan, EscrowError> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Milestones(payment_id.clone()))
+            .ok_or(EscrowError::InvalidMilestones)
     }
 
-    fn require_admin(env: &Env, caller: &Address) {
-        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
-        if caller != &admin {
-            panic!("Not admin");
+    fn require_no_milestones(env: &Env, payment_id: &BytesN<32>) -> Result<(), EscrowError> {
+        if Self::has_milestones(env, payment_id) {
+            return Err(EscrowError::ReleasedByMilestone);
         }
+        Ok(())
     }
 
-    fn require_releasable(env: &Env, payment: &PaymentEscrow) {
-        if payment.status == PaymentStatus::Disputed {
-            panic!("Dispute is open");
-        }
-        if payment.status == PaymentStatus::Released {
-            panic!("Payment fully released");
+    /// Streams pay out as they vest and hashlocked escrows on the preimage,
+    /// never in an operator-chosen lump sum.
+    fn require_lump_sum(payment: &PaymentEscrow) -> Result<(), EscrowError> {
+        if payment.stream_start.is_some() || payment.hashlock.is_some() {
+            return Err(EscrowError::UnsupportedEscrowKind);
         }
-        if payment.status == PaymentStatus::Expired {
-            panic!("Payment expired");
+        Ok(())
+    }
+
+    /// Amount of a streamed escrow vested by the current ledger.
+    fn vested_amount(env: &Env, payment: &PaymentEscrow) -> Result<i128, EscrowError> {
+        let (start, end) = match (payment.stream_start, payment.stream_end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(EscrowError::UnsupportedEscrowKind),
+        };
+        let now = env.ledger().sequence();
+        if now >= end {
+            return Ok(payment.amount);
         }
-        if env.ledger().sequence() > payment.expiry {
-            panic!("Payment expired");
+        if now <= start {
+            return Ok(0);
         }
+        let elapsed = i128::from(now - start);
+        let duration = i128::from(end - start);
+        Ok(payment.amount.saturating_mul(elapsed) / duration)
     }
 
-    fn require_expirable(env: &Env, payment: &PaymentEscrow) {
-        if payment.status == PaymentStatus::Disputed {
-            panic!("Dispute is open");
+    /// Vested amount not yet paid out.  Refunds made by `merchant_refund`
+    /// count as paid out, so they come out of the merchant's vested share.
+    fn withdrawable_amount(env: &Env, payment: &PaymentEscrow) -> Result<i128, EscrowError> {
+        let vested = Self::vested_amount(env, payment)?;
+        Ok(vested.saturating_sub(payment.released_amount).max(0))
+    }
+
+    fn has_milestones(env: &Env, payment_id: &BytesN<32>) -> bool {
+        env.storage()
+            .persistent()
+            .has(&DataKey::Milestones(payment_id.clone()))
+    }
+
+    /// Mark a pending milestone with `outcome` and return it as it was.
+    fn take_pending_milestone(
+        plan: &mut MilestonePlan,
+        index: u32,
+        outcome: MilestoneStatus,
+    ) -> Result<Milestone, EscrowError> {
+        let mut milestone = plan
+            .milestones
+            .get(index)
+            .ok_or(EscrowError::InvalidMilestones)?;
+        if milestone.status != MilestoneStatus::Pending {
+            return Err(EscrowError::MilestoneAlreadySettled);
         }
-        if payment.status == PaymentStatus::Released {
-            panic!("Payment fully released");
+        let taken = milestone.clone();
+        milestone.status = outcome;
+        plan.milestones.set(index, milestone);
+        Ok(taken)
+    }
+
+    /// Persist a settled milestone and close the escrow once none are pending.
+    /// The escrow ends `Released` if any milestone reached the merchant.
+    fn finish_milestone(env: &Env, payment: &mut PaymentEscrow, plan: &MilestonePlan, paid: i128) {
+        payment.released_amount += paid;
+        if plan
+            .milestones
+            .iter()
+            .all(|m| m.status != MilestoneStatus::Pending)
+        {
+            payment.status = if plan
+                .milestones
+                .iter()
+                .any(|m| m.status == MilestoneStatus::Released)
+            {
+                PaymentStatus::Released
+            } else {
+                PaymentStatus::Expired
+            };
         }
-        if payment.status != PaymentStatus::Pending {
-            panic!("Payment is not pending");
+
+        env.storage().persistent().set(
+            &DataKey::Milestones(payment.payment_id.clone()),
+            plan,
+        );
+        Self::save_payment(env, payment);
+    }
+
+    /// Mark pending milestones past their deadline `Released` when the plan
+    /// defaults to release, and return their total.
+    fn release_due_milestones(env: &Env, payment_id: &BytesN<32>) -> i128 {
+        let key = DataKey::Milestones(payment_id.clone());
+        let mut plan: MilestonePlan = match env.storage().persistent().get(&key) {
+            Some(plan) => plan,
+            None => return 0,
+        };
+        if plan.on_deadline != MilestoneDefault::Release {
+            return 0;
         }
-        if env.ledger().sequence() <= payment.expiry {
-            panic!("Payment has not expired");
+        let mut due: i128 = 0;
+        for i in 0..plan.milestones.len() {
+            let mut milestone = plan.milestones.get(i).unwrap();
+            if milestone.status == MilestoneStatus::Pending
+                && env.ledger().sequence() > milestone.deadline
+            {
+                due += milestone.amount;
+                milestone.status = MilestoneStatus::Released;
+                plan.milestones.set(i, milestone);
+            }
         }
+        env.storage().persistent().set(&key, &plan);
+        due
     }
 
-    fn remaining_amount(payment: &PaymentEscrow) -> i128 {
-        payment.amount.saturating_sub(payment.released_amount)
+    /// Mark every still-pending milestone with `outcome` when the escrow is
+    /// closed as a whole (refund, expiry or dispute resolution).
+    fn close_open_milestones(env: &Env, payment_id: &BytesN<32>, outcome: MilestoneStatus) {
+        let key = DataKey::Milestones(payment_id.clone());
+        let mut plan: MilestonePlan = match env.storage().persistent().get(&key) {
+            Some(plan) => plan,
+            None => return,
+        };
+        for i in 0..plan.milestones.len() {
+            let mut milestone = plan.milestones.get(i).unwrap();
+            if milestone.status == MilestoneStatus::Pending {
+                milestone.status = outcome.clone();
+                plan.milestones.set(i, milestone);
+            }
+        }
+        env.storage().persistent().set(&key, &plan);
     }
 
-    fn transfer_from_contract(env: &Env, recipient: &Address, amount: i128, asset_type: &AssetType) {
-        let token_addr = Self::token_address(env, asset_type);
-        token::Client::new(env, &token_addr)
+    fn transfer_from_contract(env: &Env, recipient: &Address, amount: i128, token: &Address) {
+        token::Client::new(env, token)
             .transfer(&env.current_contract_address(), recipient, &amount);
     }
 
-    fn require_emergency_signer(env: &Env, signer: &Address) {
-        let signers: Vec<Address> = env
-            .storage()
-            .instance()
-            .get(&DataKey::EmergencySigners)
-            .unwrap();
-        if signers.iter().all(|configured| configured != *signer) {
-            panic!("Not emergency signer");
+    fn require_emergency_signer(env: &Env, signer: &Address) -> Result<(), EscrowError> {
+        let signers = Self::get_emergency_signers(env.clone())?;
+        if !signers.contains(signer) {
+            return Err(EscrowError::MissingRole);
+        }
+        Ok(())
+    }
+
+    /// Check that `signers` are distinct members of the emergency set, that
+    /// there are enough of them, and that each one authorized the call.
+    fn require_emergency_quorum(
+        env: &Env,
+        caller: &Address,
+        signers: &Vec<Address>,
+    ) -> Result<(), EscrowError> {
+        for (i, signer) in signers.iter().enumerate() {
+            if signers.first_index_of(&signer) != Some(i as u32) {
+                return Err(EscrowError::InvalidConfig);
+            }
+            Self::require_emergency_signer(env, &signer)?;
+            // A signer may also be the caller; an address can only authorize
+            // a frame once, so skip the duplicate `require_auth` in that case.
+            if signer != *caller {
+                signer.require_auth();
+            }
+        }
+        if signers.len() < Self::get_emergency_threshold(env.clone())? {
+            return Err(EscrowError::EmergencyQuorumNotMet);
+        }
+        Ok(())
+    }
+
+    fn validate_signer_set(signers: &Vec<Address>, threshold: u32) -> Result<(), EscrowError> {
+        if signers.len() > MAX_EMERGENCY_SIGNERS {
+            return Err(EscrowError::LimitExceeded);
+        }
+        for (i, signer) in signers.iter().enumerate() {
+            if signers.first_index_of(&signer) != Some(i as u32) {
+                return Err(EscrowError::InvalidConfig);
+            }
+        }
+        if threshold < MIN_EMERGENCY_THRESHOLD || threshold > signers.len() {
+            return Err(EscrowError::InvalidConfig);
         }
+        Ok(())
     }
 }
@@ -1,30 +1,47 @@
 #![cfg(test)]
 
-use crate::{AssetType, PaymentEscrowContract, PaymentEscrowContractClient, PaymentStatus};
+use crate::{
+    AssetType, AttestationPayload, DataKey, DisputeResolvedEvent, DisputeTimeoutEvent,
+    EscrowError, EscrowEvent, EscrowIndex, FeeHooks, LegacyPaymentEscrow, MilestoneDefault,
+    MilestoneInput, MilestoneStatus, PauseScope, PaymentEscrowContract,
+    PaymentEscrowContractClient, PaymentStatus, Role, SplitRecipientInput, SplitShare,
+    SubscriptionStatus, TokenLimits, EVENT_SCHEMA_VERSION,
+};
+use ed25519_dalek::{Signer, SigningKey};
+use fee_calculator::{FeeCalculatorContract, FeeTier};
+use fee_distributor::FeeDistributorContract;
 use merchant_registry::{MerchantRegistryContract, MerchantRegistryContractClient};
+use payment_request::{
+    PaymentRequestContract, PaymentRequestContractClient, PaymentStatus as InvoiceStatus,
+};
+use settlement_ledger::{SettlementLedgerContract, SettlementLedgerContractClient};
 use soroban_sdk::{
-    testutils::Address as _, testutils::Ledger, token, Address, BytesN, Env, String,
+    symbol_short,
+    testutils::storage::{Instance as _, Persistent as _},
+    testutils::Address as _,
+    testutils::{Events as _, Ledger},
+    token, vec,
+    xdr::ToXdr,
+    Address, Bytes, BytesN, Env, Map, String, Symbol, TryFromVal, Val, Vec,
 };
 
 const DEFAULT_PAYMENT_TTL: u32 = 100;
 const EMERGENCY_COOLDOWN_LEDGERS: u32 = 50;
+const EMERGENCY_THRESHOLD: u32 = 2;
 
 fn setup_env() -> (
     Env,
     PaymentEscrowContractClient<'static>,
-    Address,
-    Address,
-    Address,
-    Address,
-    Address,
-<<<<<<< HEAD
+    Address, // contract_id
+    Address, // admin
+    Address, // customer
+    Address, // merchant
+    Address, // usdc
     Address, // xlm
-=======
-    Address,
-    Address,
-    Address,
-    Address,
->>>>>>> pr-956-head
+    Address, // emergency_signer_one
+    Address, // emergency_signer_two
+    Address, // emergency_signer_three
+    Address, // emergency_treasury
 ) {
     let env = Env::default();
     env.mock_all_auths();
@@ -34,20 +51,11 @@ fn setup_env() -> (
     let customer = Address::generate(&env);
     let merchant = Address::generate(&env);
     let token_admin = Address::generate(&env);
-<<<<<<< HEAD
 
     let usdc_asset = env.register_stellar_asset_contract_v2(token_admin.clone());
     let usdc = usdc_asset.address();
-
-    let xlm_asset = env.register_stellar_asset_contract_v2(token_admin.clone());
+    let xlm_asset = env.register_stellar_asset_contract_v2(token_admin);
     let xlm = xlm_asset.address();
-
-    let contract_id = env.register(
-        PaymentEscrowContract,
-        (&admin, &xlm, &usdc, &DEFAULT_PAYMENT_TTL, &Option::<Address>::None),
-=======
-    let asset_contract = env.register_stellar_asset_contract_v2(token_admin);
-    let usdc = asset_contract.address();
     let emergency_signer_one = Address::generate(&env);
     let emergency_signer_two = Address::generate(&env);
     let emergency_signer_three = Address::generate(&env);
@@ -63,23 +71,22 @@ fn setup_env() -> (
         PaymentEscrowContract,
         (
             &admin,
+            &xlm,
             &usdc,
             &DEFAULT_PAYMENT_TTL,
             &Option::<Address>::None,
-            &emergency_signers,
+            emergency_signers,
+            &EMERGENCY_THRESHOLD,
             &emergency_treasury,
             &EMERGENCY_COOLDOWN_LEDGERS,
         ),
->>>>>>> pr-956-head
     );
     let client = PaymentEscrowContractClient::new(&env, &contract_id);
 
-    // Mint USDC for customer (used by existing tests)
+    // Mint USDC and XLM for customer.
     token::StellarAssetClient::new(&env, &usdc).mint(&customer, &1_000_000_000i128);
+    token::StellarAssetClient::new(&env, &xlm).mint(&customer, &1_000_000_000i128);
 
-<<<<<<< HEAD
-    (env, client, contract_id, admin, customer, merchant, usdc, xlm)
-=======
     (
         env,
         client,
@@ -88,12 +95,12 @@ fn setup_env() -> (
         customer,
         merchant,
         usdc,
+        xlm,
         emergency_signer_one,
         emergency_signer_two,
         emergency_signer_three,
         emergency_treasury,
     )
->>>>>>> pr-956-head
 }
 
 fn make_id(env: &Env, seed: u8) -> BytesN<32> {
@@ -114,16 +121,13 @@ fn deposit_default_ttl(
         &amount,
         &DEFAULT_PAYMENT_TTL,
         &AssetType::Usdc,
+        &None,
     );
 }
 
 #[test]
 fn test_constructor() {
-<<<<<<< HEAD
-    let (_env, client, _contract_id, admin, _customer, _merchant, usdc, xlm) = setup_env();
-=======
-    let (_env, client, _contract_id, admin, _customer, _merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
+    let (_env, client, _contract_id, admin, _customer, _merchant, usdc, xlm, ..) = setup_env();
 
     assert_eq!(client.get_admin(), admin);
     assert_eq!(client.get_usdc_token(), usdc);
@@ -133,11 +137,7 @@ fn test_constructor() {
 
 #[test]
 fn test_deposit_happy_path() {
-<<<<<<< HEAD
-    let (env, client, contract_id, _admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     let result = client.deposit(
@@ -147,6 +147,7 @@ fn test_deposit_happy_path() {
         &250_000_000i128,
         &DEFAULT_PAYMENT_TTL,
         &AssetType::Usdc,
+        &None,
     );
     let payment = client.get_payment(&payment_id);
 
@@ -170,7 +171,7 @@ fn test_deposit_happy_path() {
 
 #[test]
 fn test_lifecycle_create_deposit_confirm_settle() {
-    let (env, client, contract_id, admin, customer, merchant, usdc) = setup_env();
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
     let payment_id = make_id(&env, 2);
 
     // create
@@ -201,85 +202,95 @@ fn test_lifecycle_create_deposit_confirm_settle() {
 }
 
 #[test]
-#[should_panic(expected = "Payment ID already exists")]
 fn test_deposit_duplicate_payment_id() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &payment_id,
+            &merchant,
+            &250_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::PaymentAlreadyExists))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Amount must be > 0")]
 fn test_deposit_zero_amount() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
 
-    client.deposit(
-        &customer,
-        &make_id(&env, 1),
-        &merchant,
-        &0i128,
-        &DEFAULT_PAYMENT_TTL,
-        &AssetType::Usdc,
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 1),
+            &merchant,
+            &0i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::InvalidAmount))
     );
 }
 
 #[test]
-#[should_panic(expected = "TTL must be > 0")]
 fn test_deposit_zero_ttl() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
 
-    client.deposit(
-        &customer,
-        &make_id(&env, 1),
-        &merchant,
-        &250_000_000i128,
-        &0u32,
-        &AssetType::Usdc,
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 1),
+            &merchant,
+            &250_000_000i128,
+            &0u32,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::InvalidTtl))
     );
 }
 
 #[test]
-#[should_panic(expected = "TTL exceeds maximum")]
 fn test_deposit_excessive_ttl() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let ttl = client.get_max_ttl_ledgers() + 1;
 
-    client.deposit(
-        &customer,
-        &make_id(&env, 1),
-        &merchant,
-        &250_000_000i128,
-        &ttl,
-        &AssetType::Usdc,
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 1),
+            &merchant,
+            &250_000_000i128,
+            &ttl,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::InvalidTtl))
     );
 }
 
 #[test]
-<<<<<<< HEAD
 fn test_deposit_max_ttl_boundary() {
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc) = setup_env();
+    let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
     let payment_id = make_id(&env, 88);
     let max_ttl = client.get_max_ttl_ledgers();
 
-    client.deposit(&customer, &payment_id, &merchant, &250_000_000i128, &max_ttl);
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &250_000_000i128,
+        &max_ttl,
+        &AssetType::Usdc,
+        &None,
+    );
 
     let payment = client.get_payment(&payment_id);
     assert_eq!(payment.expiry, 10 + max_ttl);
@@ -287,10 +298,18 @@ fn test_deposit_max_ttl_boundary() {
 
 #[test]
 fn test_deposit_minimum_positive_amount_boundary() {
-    let (env, client, contract_id, _admin, customer, merchant, usdc) = setup_env();
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
     let payment_id = make_id(&env, 89);
 
-    client.deposit(&customer, &payment_id, &merchant, &1i128, &DEFAULT_PAYMENT_TTL);
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &1i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
+    );
 
     let payment = client.get_payment(&payment_id);
     assert_eq!(payment.amount, 1);
@@ -303,26 +322,63 @@ fn test_deposit_minimum_positive_amount_boundary() {
 }
 
 #[test]
-fn test_get_expiry_with_short_ttl() {
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
+fn test_deposit_and_release_xlm() {
+    let (env, client, contract_id, admin, customer, merchant, _usdc, xlm, ..) = setup_env();
+    let payment_id = make_id(&env, 3);
+
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &250_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Xlm,
+        &None,
+    );
+    assert_eq!(client.get_payment(&payment_id).asset_type, AssetType::Xlm);
+
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &xlm);
+    assert_eq!(token_client.balance(&merchant), 250_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_deposit_and_release_usdc() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, xlm, ..) = setup_env();
+    let payment_id = make_id(&env, 4);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.release(&admin, &payment_id);
+
+    let usdc_client = token::Client::new(&env, &usdc);
+    assert_eq!(usdc_client.balance(&merchant), 250_000_000);
+    assert_eq!(usdc_client.balance(&contract_id), 0);
+    assert_eq!(token::Client::new(&env, &xlm).balance(&merchant), 0);
+}
+
+#[test]
 fn test_get_expiry_with_short_ttl() {
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
-    client.deposit(&customer, &payment_id, &merchant, &250_000_000i128, &5u32, &AssetType::Usdc);
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &250_000_000i128,
+        &5u32,
+        &AssetType::Usdc,
+        &None,
+    );
 
     assert_eq!(client.get_expiry(&payment_id), 15);
 }
 
 #[test]
 fn test_get_expiry_with_long_ttl() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     client.deposit(
@@ -332,6 +388,7 @@ fn test_get_expiry_with_long_ttl() {
         &250_000_000i128,
         &2_000u32,
         &AssetType::Usdc,
+        &None,
     );
 
     assert_eq!(client.get_expiry(&payment_id), 2_010);
@@ -339,11 +396,7 @@ fn test_get_expiry_with_long_ttl() {
 
 #[test]
 fn test_release_happy_path() {
-<<<<<<< HEAD
-    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -361,11 +414,7 @@ fn test_release_happy_path() {
 
 #[test]
 fn test_partial_release_multiple_steps() {
-<<<<<<< HEAD
-    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -389,27 +438,21 @@ fn test_partial_release_multiple_steps() {
 }
 
 #[test]
-#[should_panic(expected = "Release amount exceeds remaining balance")]
 fn test_partial_release_prevents_over_release() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     client.release_partial(&admin, &payment_id, &200_000_000i128);
-    client.release_partial(&admin, &payment_id, &100_000_000i128);
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &100_000_000i128),
+        Err(Ok(EscrowError::InvalidAmount))
+    );
 }
 
 #[test]
 fn test_refund_returns_remaining_balance_after_partial_release() {
-<<<<<<< HEAD
-    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -428,130 +471,101 @@ fn test_refund_returns_remaining_balance_after_partial_release() {
 }
 
 #[test]
-#[should_panic(expected = "Not admin")]
 fn test_release_unauthorized() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
     let random = Address::generate(&env);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    client.release(&random, &payment_id);
+    assert_eq!(client.try_release(&random, &payment_id), Err(Ok(EscrowError::NotAdmin)));
 }
 
 #[test]
-#[should_panic(expected = "Not admin")]
 fn test_partial_release_unauthorized() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
     let random = Address::generate(&env);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    client.release_partial(&random, &payment_id, &10_000_000i128);
+    assert_eq!(
+        client.try_release_partial(&random, &payment_id, &10_000_000i128),
+        Err(Ok(EscrowError::NotAdmin))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Payment not found")]
 fn test_release_invalid_payment_id() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, _customer, _merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, _customer, _merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
 
-    client.release(&admin, &make_id(&env, 99));
+    assert_eq!(
+        client.try_release(&admin, &make_id(&env, 99)),
+        Err(Ok(EscrowError::PaymentNotFound))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Payment expired")]
 fn test_release_expired_payment() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     env.ledger().set_sequence_number(111);
 
-    client.release(&admin, &payment_id);
+    assert_eq!(client.try_release(&admin, &payment_id), Err(Ok(EscrowError::PaymentExpired)));
 }
 
 #[test]
-#[should_panic(expected = "Payment expired")]
 fn test_partial_release_expired_payment() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     env.ledger().set_sequence_number(111);
 
-    client.release_partial(&admin, &payment_id, &10_000_000i128);
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &10_000_000i128),
+        Err(Ok(EscrowError::PaymentExpired))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Payment fully released")]
 fn test_release_already_settled_payment() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     client.release(&admin, &payment_id);
-    client.release(&admin, &payment_id);
+    assert_eq!(client.try_release(&admin, &payment_id), Err(Ok(EscrowError::PaymentFullyReleased)));
 }
 
 #[test]
-#[should_panic(expected = "Release amount must be > 0")]
 fn test_partial_release_zero_amount() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    client.release_partial(&admin, &payment_id, &0i128);
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &0i128),
+        Err(Ok(EscrowError::InvalidAmount))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Payment fully released")]
 fn test_partial_release_fully_released_payment() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     client.release(&admin, &payment_id);
-    client.release_partial(&admin, &payment_id, &1i128);
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &1i128),
+        Err(Ok(EscrowError::PaymentFullyReleased))
+    );
 }
 
 #[test]
 fn test_expire_refunds_customer() {
-<<<<<<< HEAD
-    let (env, client, contract_id, _admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -568,26 +582,17 @@ fn test_expire_refunds_customer() {
 }
 
 #[test]
-#[should_panic(expected = "Payment has not expired")]
 fn test_expire_before_ttl() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    client.expire(&payment_id);
+    assert_eq!(client.try_expire(&payment_id), Err(Ok(EscrowError::PaymentNotExpired)));
 }
 
 #[test]
 fn test_dispute_by_customer() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -608,11 +613,7 @@ fn test_dispute_by_customer() {
 
 #[test]
 fn test_dispute_by_merchant() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -627,53 +628,47 @@ fn test_dispute_by_merchant() {
 }
 
 #[test]
-#[should_panic(expected = "Not payment participant")]
 fn test_dispute_unauthorized_caller() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
     let random = Address::generate(&env);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
-    client.dispute(&random, &payment_id, &String::from_str(&env, "not allowed"));
+    assert_eq!(
+        client.try_dispute(&random, &payment_id, &String::from_str(&env, "not allowed")),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Dispute already open")]
 fn test_duplicate_dispute_rejected() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     client.dispute(&customer, &payment_id, &String::from_str(&env, "first"));
-    client.dispute(&merchant, &payment_id, &String::from_str(&env, "second"));
+    assert_eq!(
+        client.try_dispute(&merchant, &payment_id, &String::from_str(&env, "second")),
+        Err(Ok(EscrowError::DisputeOpen))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Dispute window expired")]
 fn test_dispute_after_window_rejected() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     env.ledger().set_sequence_number(111);
-    client.dispute(&customer, &payment_id, &String::from_str(&env, "too late"));
+    assert_eq!(
+        client.try_dispute(&customer, &payment_id, &String::from_str(&env, "too late")),
+        Err(Ok(EscrowError::DisputeWindowExpired))
+    );
 }
 
 #[test]
 fn test_dispute_allowed_at_dispute_window_boundary() {
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc) = setup_env();
+    let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
     let payment_id = make_id(&env, 90);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -689,13 +684,8 @@ fn test_dispute_allowed_at_dispute_window_boundary() {
 }
 
 #[test]
-#[should_panic(expected = "Dispute is open")]
 fn test_release_blocked_while_disputed() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -704,17 +694,12 @@ fn test_release_blocked_while_disputed() {
         &payment_id,
         &String::from_str(&env, "hold funds"),
     );
-    client.release(&admin, &payment_id);
+    assert_eq!(client.try_release(&admin, &payment_id), Err(Ok(EscrowError::DisputeOpen)));
 }
 
 #[test]
-#[should_panic(expected = "Dispute is open")]
 fn test_partial_release_blocked_while_disputed() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -723,17 +708,15 @@ fn test_partial_release_blocked_while_disputed() {
         &payment_id,
         &String::from_str(&env, "hold release"),
     );
-    client.release_partial(&admin, &payment_id, &10_000_000i128);
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &10_000_000i128),
+        Err(Ok(EscrowError::DisputeOpen))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Dispute is open")]
 fn test_expire_blocked_while_disputed() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -743,16 +726,12 @@ fn test_expire_blocked_while_disputed() {
         &String::from_str(&env, "hold refund"),
     );
     env.ledger().set_sequence_number(111);
-    client.expire(&payment_id);
+    assert_eq!(client.try_expire(&payment_id), Err(Ok(EscrowError::DisputeOpen)));
 }
 
 #[test]
 fn test_resolve_dispute_to_customer() {
-<<<<<<< HEAD
-    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
     let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -773,9 +752,8 @@ fn test_resolve_dispute_to_customer() {
 }
 
 #[test]
-<<<<<<< HEAD
 fn test_cancellation_path_refunds_customer_via_dispute_resolution() {
-    let (env, client, contract_id, admin, customer, merchant, usdc) = setup_env();
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
     let payment_id = make_id(&env, 91);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -797,12 +775,8 @@ fn test_cancellation_path_refunds_customer_via_dispute_resolution() {
 }
 
 #[test]
-fn test_resolve_dispute_to_merchant() {
-    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm) = setup_env();
-=======
 fn test_resolve_dispute_to_merchant() {
     let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -823,13 +797,8 @@ fn test_resolve_dispute_to_merchant() {
 }
 
 #[test]
-#[should_panic(expected = "Not admin")]
 fn test_resolve_dispute_unauthorized() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, _admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
     let random = Address::generate(&env);
 
@@ -839,27 +808,24 @@ fn test_resolve_dispute_unauthorized() {
         &payment_id,
         &String::from_str(&env, "investigate"),
     );
-    client.resolve_dispute(&random, &payment_id, &merchant);
+    assert_eq!(
+        client.try_resolve_dispute(&random, &payment_id, &merchant),
+        Err(Ok(EscrowError::NotAdmin))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Not admin")]
 fn test_set_registry_unauthorized() {
-    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc) = setup_env();
+    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc, ..) = setup_env();
     let random = Address::generate(&env);
     let registry = Address::generate(&env);
 
-    client.set_registry(&random, &Some(registry));
+    assert_eq!(client.try_set_registry(&random, &Some(registry)), Err(Ok(EscrowError::NotAdmin)));
 }
 
 #[test]
-#[should_panic(expected = "Invalid dispute winner")]
 fn test_resolve_dispute_invalid_winner() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
     let random = Address::generate(&env);
 
@@ -869,17 +835,15 @@ fn test_resolve_dispute_invalid_winner() {
         &payment_id,
         &String::from_str(&env, "investigate"),
     );
-    client.resolve_dispute(&admin, &payment_id, &random);
+    assert_eq!(
+        client.try_resolve_dispute(&admin, &payment_id, &random),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Dispute is not open")]
 fn test_resolve_dispute_already_resolved() {
-<<<<<<< HEAD
-    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm) = setup_env();
-=======
     let (env, client, _contract_id, admin, customer, merchant, _usdc, ..) = setup_env();
->>>>>>> pr-956-head
     let payment_id = make_id(&env, 1);
 
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
@@ -889,7 +853,10 @@ fn test_resolve_dispute_already_resolved() {
         &String::from_str(&env, "investigate"),
     );
     client.resolve_dispute(&admin, &payment_id, &merchant);
-    client.resolve_dispute(&admin, &payment_id, &merchant);
+    assert_eq!(
+        client.try_resolve_dispute(&admin, &payment_id, &merchant),
+        Err(Ok(EscrowError::DisputeNotOpen))
+    );
 }
 
 // ---------------------------------------------------------------------------
@@ -904,6 +871,7 @@ fn setup_with_registry() -> (
     Address, // customer
     Address, // merchant
     Address, // usdc
+    Address, // xlm
     Address, // emergency_signer_one
     Address, // emergency_signer_two
     Address, // emergency_signer_three
@@ -917,15 +885,11 @@ fn setup_with_registry() -> (
     let customer = Address::generate(&env);
     let merchant = Address::generate(&env);
     let token_admin = Address::generate(&env);
-<<<<<<< HEAD
 
     let usdc_asset = env.register_stellar_asset_contract_v2(token_admin.clone());
     let usdc = usdc_asset.address();
     let xlm_asset = env.register_stellar_asset_contract_v2(token_admin);
     let xlm = xlm_asset.address();
-=======
-    let asset_contract = env.register_stellar_asset_contract_v2(token_admin);
-    let usdc = asset_contract.address();
     let emergency_signer_one = Address::generate(&env);
     let emergency_signer_two = Address::generate(&env);
     let emergency_signer_three = Address::generate(&env);
@@ -936,7 +900,6 @@ fn setup_with_registry() -> (
         emergency_signer_two.clone(),
         emergency_signer_three.clone(),
     ];
->>>>>>> pr-956-head
 
     // Deploy registry and register the merchant.
     let registry_id = env.register(MerchantRegistryContract, (&admin,));
@@ -946,19 +909,17 @@ fn setup_with_registry() -> (
     // Deploy escrow wired to registry.
     let escrow_id = env.register(
         PaymentEscrowContract,
-<<<<<<< HEAD
-        (&admin, &xlm, &usdc, &DEFAULT_PAYMENT_TTL, &Some(registry_id.clone())),
-=======
         (
             &admin,
+            &xlm,
             &usdc,
             &DEFAULT_PAYMENT_TTL,
             &Some(registry_id.clone()),
-            &emergency_signers,
+            emergency_signers,
+            &EMERGENCY_THRESHOLD,
             &emergency_treasury,
             &EMERGENCY_COOLDOWN_LEDGERS,
         ),
->>>>>>> pr-956-head
     );
     let escrow = PaymentEscrowContractClient::new(&env, &escrow_id);
 
@@ -973,6 +934,7 @@ fn setup_with_registry() -> (
         customer,
         merchant,
         usdc,
+        xlm,
         emergency_signer_one,
         emergency_signer_two,
         emergency_signer_three,
@@ -992,6 +954,7 @@ fn test_deposit_allowed_for_active_merchant() {
         &100_000_000i128,
         &DEFAULT_PAYMENT_TTL,
         &AssetType::Usdc,
+        &None,
     );
 
     let payment = escrow.get_payment(&payment_id);
@@ -1000,20 +963,23 @@ fn test_deposit_allowed_for_active_merchant() {
 }
 
 #[test]
-#[should_panic(expected = "Merchant is suspended or not registered")]
 fn test_deposit_blocked_for_suspended_merchant() {
     let (env, escrow, registry, admin, customer, merchant, _usdc, ..) = setup_with_registry();
     let payment_id = make_id(&env, 43);
 
     registry.suspend_merchant(&admin, &merchant);
 
-    escrow.deposit(
-        &customer,
-        &payment_id,
-        &merchant,
-        &100_000_000i128,
-        &DEFAULT_PAYMENT_TTL,
-        &AssetType::Usdc,
+    assert_eq!(
+        escrow.try_deposit(
+            &customer,
+            &payment_id,
+            &merchant,
+            &100_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::MerchantNotActive))
     );
 }
 
@@ -1032,6 +998,7 @@ fn test_deposit_allowed_after_reactivation() {
         &100_000_000i128,
         &DEFAULT_PAYMENT_TTL,
         &AssetType::Usdc,
+        &None,
     );
 
     let payment = escrow.get_payment(&payment_id);
@@ -1039,40 +1006,53 @@ fn test_deposit_allowed_after_reactivation() {
 }
 
 #[test]
-#[should_panic(expected = "Merchant is suspended or not registered")]
 fn test_deposit_blocked_for_unregistered_merchant() {
     let (env, escrow, _registry, _admin, customer, _merchant, _usdc, ..) = setup_with_registry();
     let payment_id = make_id(&env, 45);
     let unknown_merchant = Address::generate(&env);
 
+    assert_eq!(
+        escrow.try_deposit(
+            &customer,
+            &payment_id,
+            &unknown_merchant,
+            &100_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::MerchantNotActive))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Merchant registry integration: release gating
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_release_blocked_for_unverified_merchant() {
+    let (env, escrow, _registry, admin, customer, merchant, _usdc, ..) = setup_with_registry();
+    let payment_id = make_id(&env, 50);
+
     escrow.deposit(
         &customer,
         &payment_id,
-        &unknown_merchant,
-        &100_000_000i128,
+        &merchant,
+        &250_000_000i128,
         &DEFAULT_PAYMENT_TTL,
         &AssetType::Usdc,
+        &None,
+    );
+    assert_eq!(
+        escrow.try_release(&admin, &payment_id),
+        Err(Ok(EscrowError::MerchantNotKycVerified))
     );
 }
 
 #[test]
-fn test_emergency_drain_moves_all_funds_to_treasury() {
-    let (
-        env,
-        client,
-        contract_id,
-        _admin,
-        customer,
-        merchant,
-        usdc,
-        emergency_signer_one,
-        emergency_signer_two,
-        _emergency_signer_three,
-        emergency_treasury,
-    ) = setup_env();
-
+fn test_release_allowed_for_verified_merchant() {
+    let (env, escrow, registry, admin, customer, merchant, usdc, ..) = setup_with_registry();
     let payment_id = make_id(&env, 51);
-<<<<<<< HEAD
 
     // Verify the merchant first.
     registry.set_kyc_status(&admin, &merchant, &true);
@@ -1083,6 +1063,8 @@ fn test_emergency_drain_moves_all_funds_to_treasury() {
         &merchant,
         &250_000_000i128,
         &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
     );
     escrow.release(&admin, &payment_id);
 
@@ -1095,50 +1077,31 @@ fn test_emergency_drain_moves_all_funds_to_treasury() {
 }
 
 #[test]
-fn test_version_initialization() {
-    let (_env, client, _contract_id, _admin, _customer, _merchant, _usdc) = setup_env();
-    assert_eq!(client.get_version(), 1);
-}
-
-#[test]
-#[should_panic(expected = "Not admin")]
-fn test_upgrade_unauthorized() {
-    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc) = setup_env();
-    let random = Address::generate(&env);
-    let dummy_hash = BytesN::from_array(&env, &[0; 32]);
-
-    client.upgrade(&random, &dummy_hash);
-}
+fn test_partial_release_blocked_for_unverified_merchant() {
+    let (env, escrow, _registry, admin, customer, merchant, _usdc, ..) = setup_with_registry();
+    let payment_id = make_id(&env, 52);
 
-#[test]
-fn test_upgrade_version_increment_stub() {
-    let (env, client, _contract_id, admin, _customer, _merchant, _usdc) = setup_env();
-    
-    assert_eq!(client.get_version(), 1);
+    escrow.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &200_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
+    );
 
-    // We use a dummy hash here. In a real environment or a full integration test,
-    // this would be a valid WASM hash uploaded to the network.
-    // For the purpose of this stub test, we are verifying that the version increments
-    // before the actual WASM update call. 
-    // Note: If update_current_contract_wasm panics on an invalid hash in the test environment,
-    // this test might fail, but it serves as the required "v2 stub".
-    
-    // To make it not fail on the update call if it validates hashes, we'd need a real hash.
-    // Since we don't have one, we'll just check if it increments.
-    // In Soroban's current test environment, update_current_contract_wasm might not
-    // strictly validate the hash if it's not a full integration test.
-    
-    let dummy_hash = BytesN::from_array(&env, &[0; 32]);
-    client.upgrade(&admin, &dummy_hash);
-    
-    assert_eq!(client.get_version(), 2);
+    // Partial release must also be blocked.
+    assert_eq!(
+        escrow.try_release_partial(&admin, &payment_id, &50_000_000i128),
+        Err(Ok(EscrowError::MerchantNotKycVerified))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Merchant not KYC verified")]
-fn test_partial_release_blocked_for_unverified_merchant() {
-    let (env, escrow, _registry, admin, customer, merchant, _usdc) = setup_with_registry();
-    let payment_id = make_id(&env, 52);
+fn test_dispute_win_blocked_for_unverified_merchant() {
+    let (env, escrow, registry, admin, customer, merchant, usdc, ..) = setup_with_registry();
+    let payment_id = make_id(&env, 54);
 
     escrow.deposit(
         &customer,
@@ -1146,28 +1109,108 @@ fn test_partial_release_blocked_for_unverified_merchant() {
         &merchant,
         &200_000_000i128,
         &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
     );
+    escrow.dispute(&customer, &payment_id, &String::from_str(&env, "not delivered"));
 
-    // Partial release must also be blocked.
-    escrow.release_partial(&admin, &payment_id, &50_000_000i128);
+    // Resolving for the merchant pays out like a release, so it is gated too.
+    assert_eq!(
+        escrow.try_resolve_dispute(&admin, &payment_id, &merchant),
+        Err(Ok(EscrowError::MerchantNotKycVerified))
+    );
+    registry.set_kyc_status(&admin, &merchant, &true);
+    escrow.resolve_dispute(&admin, &payment_id, &merchant);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 200_000_000);
 }
 
 #[test]
 fn test_release_allowed_when_no_registry_configured() {
     // Without a registry, KYC is not enforced.
-    let (env, client, contract_id, admin, customer, merchant, usdc) = setup_env();
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
     let payment_id = make_id(&env, 53);
 
-=======
->>>>>>> pr-956-head
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 250_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+// ---------------------------------------------------------------------------
+// Upgrade / versioning
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_version_initialization() {
+    let (_env, client, ..) = setup_env();
+    assert_eq!(client.get_version(), 1);
+}
+
+#[test]
+fn test_upgrade_unauthorized() {
+    let (env, client, ..) = setup_env();
+    let random = Address::generate(&env);
+    let dummy_hash = BytesN::from_array(&env, &[0; 32]);
+
+    assert_eq!(client.try_upgrade(&random, &dummy_hash), Err(Ok(EscrowError::NotAdmin)));
+}
+
+/// Smallest module the host accepts as a contract: just the
+/// `contractenvmetav0` section declaring interface version 22.0.
+const EMPTY_CONTRACT_WASM: [u8; 40] = [
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x1e, 0x11, b'c', b'o', b'n', b't', b'r',
+    b'a', b'c', b't', b'e', b'n', b'v', b'm', b'e', b't', b'a', b'v', b'0', 0, 0, 0, 0, 0, 0, 0,
+    22, 0, 0, 0, 0,
+];
+
+#[test]
+fn test_upgrade_installs_wasm_and_increments_version() {
+    let (env, client, contract_id, admin, ..) = setup_env();
+    let wasm_hash = env
+        .deployer()
+        .upload_contract_wasm(Bytes::from_array(&env, &EMPTY_CONTRACT_WASM));
+
+    client.upgrade(&admin, &wasm_hash);
+
+    let version: Option<u32> =
+        env.as_contract(&contract_id, || env.storage().instance().get(&DataKey::Version));
+    assert_eq!(version, Some(2));
+    // The empty module exports nothing, so the old entry points are gone.
+    assert!(client.try_get_version().is_err());
+}
+
+// ---------------------------------------------------------------------------
+// Emergency drain
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_emergency_drain_moves_all_funds_to_treasury() {
+    let (
+        env,
+        client,
+        contract_id,
+        _admin,
+        customer,
+        merchant,
+        usdc,
+        _xlm,
+        emergency_signer_one,
+        emergency_signer_two,
+        _emergency_signer_three,
+        emergency_treasury,
+    ) = setup_env();
+
+    let payment_id = make_id(&env, 51);
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
 
     let drained = client.emergency_drain(
         &emergency_signer_one,
-        &emergency_signer_one,
-        &emergency_signer_two,
+        &vec![&env, emergency_signer_one.clone(), emergency_signer_two.clone()],
+        &vec![&env, AssetType::Usdc],
     );
-    assert_eq!(drained, 250_000_000);
+    assert_eq!(drained, vec![&env, 250_000_000i128]);
 
     let token_client = token::Client::new(&env, &usdc);
     assert_eq!(token_client.balance(&contract_id), 0);
@@ -1175,7 +1218,6 @@ fn test_release_allowed_when_no_registry_configured() {
 }
 
 #[test]
-#[should_panic(expected = "Not emergency signer")]
 fn test_emergency_drain_rejects_non_multisig_signer() {
     let (
         env,
@@ -1185,6 +1227,7 @@ fn test_emergency_drain_rejects_non_multisig_signer() {
         customer,
         merchant,
         _usdc,
+        _xlm,
         emergency_signer_one,
         _emergency_signer_two,
         _emergency_signer_three,
@@ -1195,11 +1238,17 @@ fn test_emergency_drain_rejects_non_multisig_signer() {
     deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
     let random = Address::generate(&env);
 
-    client.emergency_drain(&emergency_signer_one, &emergency_signer_one, &random);
+    assert_eq!(
+        client.try_emergency_drain(
+            &emergency_signer_one,
+            &vec![&env, emergency_signer_one.clone(), random],
+            &vec![&env, AssetType::Usdc],
+        ),
+        Err(Ok(EscrowError::MissingRole))
+    );
 }
 
 #[test]
-#[should_panic(expected = "Emergency drain cooldown active")]
 fn test_emergency_drain_cooldown_prevents_repeated_drain() {
     let (
         env,
@@ -1209,6 +1258,7 @@ fn test_emergency_drain_cooldown_prevents_repeated_drain() {
         customer,
         merchant,
         _usdc,
+        _xlm,
         emergency_signer_one,
         emergency_signer_two,
         _emergency_signer_three,
@@ -1219,15 +1269,3661 @@ fn test_emergency_drain_cooldown_prevents_repeated_drain() {
     deposit_default_ttl(&client, &customer, &payment_id_a, &merchant, 100_000_000i128);
     client.emergency_drain(
         &emergency_signer_one,
-        &emergency_signer_one,
-        &emergency_signer_two,
+        &vec![&env, emergency_signer_one.clone(), emergency_signer_two.clone()],
+        &vec![&env, AssetType::Usdc],
     );
 
-    let payment_id_b = make_id(&env, 54);
-    deposit_default_ttl(&client, &customer, &payment_id_b, &merchant, 100_000_000i128);
-    client.emergency_drain(
-        &emergency_signer_one,
-        &emergency_signer_one,
-        &emergency_signer_two,
+    assert_eq!(
+        client.try_emergency_drain(
+            &emergency_signer_one,
+            &vec![&env, emergency_signer_one.clone(), emergency_signer_two.clone()],
+            &vec![&env, AssetType::Xlm],
+        ),
+        Err(Ok(EscrowError::EmergencyCooldownActive))
+    );
+}
+
+#[test]
+fn test_emergency_drain_selected_assets_freezes_payments() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, xlm, s1, s2, _s3, treasury) =
+        setup_env();
+    let quorum = vec![&env, s1.clone(), s2.clone()];
+    let usdc_id = make_id(&env, 54);
+    let xlm_id = make_id(&env, 55);
+    let disputed_id = make_id(&env, 56);
+
+    deposit_default_ttl(&client, &customer, &usdc_id, &merchant, 100_000_000i128);
+    deposit_default_ttl(&client, &customer, &disputed_id, &merchant, 50_000_000i128);
+    client.deposit(&customer, &xlm_id, &merchant, &70_000_000i128, &100, &AssetType::Xlm, &None);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "late"));
+
+    let drained = client.emergency_drain(&s1, &quorum, &vec![&env, AssetType::Xlm]);
+    assert_eq!(drained, vec![&env, 70_000_000i128]);
+
+    assert_eq!(token::Client::new(&env, &xlm).balance(&treasury), 70_000_000);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 150_000_000);
+    assert_eq!(client.get_payment(&xlm_id).status, PaymentStatus::Frozen);
+    assert_eq!(client.get_payment(&usdc_id).status, PaymentStatus::Pending);
+
+    let records = client.get_drained_assets();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records.get(0).unwrap().token, xlm);
+    assert_eq!(records.get(0).unwrap().amount, 70_000_000);
+
+    env.ledger().with_mut(|l| l.sequence_number += EMERGENCY_COOLDOWN_LEDGERS);
+    client.emergency_drain(&s1, &quorum, &vec![&env, AssetType::Usdc]);
+    assert_eq!(client.get_payment(&usdc_id).status, PaymentStatus::Frozen);
+    assert_eq!(client.get_payment(&disputed_id).status, PaymentStatus::Frozen);
+}
+
+#[test]
+fn test_frozen_payment_cannot_be_released() {
+    let (env, client, _contract_id, admin, customer, merchant, _usdc, _xlm, s1, s2, ..) =
+        setup_env();
+    let quorum = vec![&env, s1.clone(), s2.clone()];
+    let payment_id = make_id(&env, 57);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.emergency_drain(&s1, &quorum, &vec![&env, AssetType::Usdc]);
+    assert_eq!(client.try_release(&admin, &payment_id), Err(Ok(EscrowError::PaymentFrozen)));
+}
+
+#[test]
+fn test_deposits_blocked_after_drain() {
+    let (env, client, _contract_id, _admin, customer, merchant, _usdc, _xlm, s1, s2, ..) =
+        setup_env();
+    let quorum = vec![&env, s1.clone(), s2.clone()];
+
+    deposit_default_ttl(&client, &customer, &make_id(&env, 58), &merchant, 100_000_000i128);
+    client.emergency_drain(&s1, &quorum, &vec![&env, AssetType::Usdc]);
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 59),
+            &merchant,
+            &1_000i128,
+            &100,
+            &AssetType::Xlm,
+            &None,
+        ),
+        Err(Ok(EscrowError::PaymentFrozen))
+    );
+}
+
+#[test]
+fn test_restore_asset_unfreezes_payments() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, _xlm, s1, s2, _s3, treasury) =
+        setup_env();
+    let quorum = vec![&env, s1.clone(), s2.clone()];
+    let payment_id = make_id(&env, 115);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.emergency_drain(&s1, &quorum, &vec![&env, AssetType::Usdc]);
+    client.restore_asset(&AssetType::Usdc);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&treasury), 0);
+    assert_eq!(token_client.balance(&contract_id), 100_000_000);
+    assert_eq!(client.get_drained_assets().len(), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Pending);
+
+    client.release(&admin, &payment_id);
+    assert_eq!(token_client.balance(&merchant), 100_000_000);
+    deposit_default_ttl(&client, &customer, &make_id(&env, 116), &merchant, 1_000i128);
+}
+
+#[test]
+fn test_restore_undrained_asset_rejected() {
+    let (_env, client, ..) = setup_env();
+    assert_eq!(client.try_restore_asset(&AssetType::Xlm), Err(Ok(EscrowError::AssetNotDrained)));
+}
+
+// ---------------------------------------------------------------------------
+// Token allowlist
+// ---------------------------------------------------------------------------
+
+fn register_token(env: &Env, holder: &Address) -> Address {
+    let token_admin = Address::generate(env);
+    let token = env
+        .register_stellar_asset_contract_v2(token_admin)
+        .address();
+    token::StellarAssetClient::new(env, &token).mint(holder, &1_000_000_000i128);
+    token
+}
+
+#[test]
+fn test_deposit_and_release_allowlisted_token() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+    let payment_id = make_id(&env, 60);
+
+    client.set_token_limits(&admin, &eurc, &1_000i128, &500_000_000i128);
+    assert_eq!(
+        client.get_token_limits(&eurc),
+        Some(TokenLimits {
+            min_amount: 1_000,
+            max_amount: 500_000_000,
+        })
+    );
+
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &250_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Token(eurc.clone()),
+        &None,
+    );
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.asset_type, AssetType::Token(eurc.clone()));
+    assert_eq!(payment.token, eurc);
+
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &eurc);
+    assert_eq!(token_client.balance(&merchant), 250_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_deposit_stores_token_address_for_builtin_assets() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 61);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+
+    assert_eq!(client.get_payment(&payment_id).token, usdc);
+}
+
+#[test]
+fn test_deposit_rejects_unlisted_token() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 62),
+            &merchant,
+            &250_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Token(eurc),
+            &None,
+        ),
+        Err(Ok(EscrowError::TokenNotAllowed))
+    );
+}
+
+#[test]
+fn test_deposit_rejects_removed_token() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+
+    client.set_token_limits(&admin, &eurc, &1i128, &500_000_000i128);
+    client.remove_token(&admin, &eurc);
+    assert_eq!(client.get_token_limits(&eurc), None);
+
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 63),
+            &merchant,
+            &250_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Token(eurc),
+            &None,
+        ),
+        Err(Ok(EscrowError::TokenNotAllowed))
+    );
+}
+
+#[test]
+fn test_deposit_below_token_minimum() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+
+    client.set_token_limits(&admin, &eurc, &1_000i128, &500_000_000i128);
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 64),
+            &merchant,
+            &999i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Token(eurc),
+            &None,
+        ),
+        Err(Ok(EscrowError::AmountOutOfRange))
+    );
+}
+
+#[test]
+fn test_deposit_above_builtin_asset_maximum() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+
+    client.set_token_limits(&admin, &usdc, &1i128, &100_000_000i128);
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 65),
+            &merchant,
+            &100_000_001i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::AmountOutOfRange))
+    );
+}
+
+#[test]
+fn test_set_token_limits_rejects_inverted_range() {
+    let (env, client, _contract_id, admin, customer, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+
+    assert_eq!(
+        client.try_set_token_limits(&admin, &eurc, &1_000i128, &999i128),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+}
+
+#[test]
+fn test_set_token_limits_unauthorized() {
+    let (env, client, _contract_id, _admin, customer, ..) = setup_env();
+    let eurc = register_token(&env, &customer);
+
+    assert_eq!(
+        client.try_set_token_limits(&customer, &eurc, &1i128, &1_000i128),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+}
+
+#[test]
+fn test_legacy_payment_record_still_releases() {
+    let (env, client, contract_id, admin, _customer, merchant, _usdc, xlm, ..) = setup_env();
+    let payment_id = make_id(&env, 66);
+    let customer = Address::generate(&env);
+
+    // Simulate an escrow written by a pre-upgrade WASM version.
+    token::StellarAssetClient::new(&env, &xlm).mint(&contract_id, &250_000_000i128);
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(
+            &DataKey::Payment(payment_id.clone()),
+            &LegacyPaymentEscrow {
+                payment_id: payment_id.clone(),
+                amount: 250_000_000,
+                released_amount: 0,
+                merchant: merchant.clone(),
+                customer: customer.clone(),
+                status: PaymentStatus::Pending,
+                expiry: 110,
+                dispute_window_end: 110,
+                dispute_reason: None,
+                asset_type: AssetType::Xlm,
+            },
+        );
+    });
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.token, xlm);
+
+    client.release(&admin, &payment_id);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Released);
+    assert_eq!(token::Client::new(&env, &xlm).balance(&merchant), 250_000_000);
+}
+
+#[test]
+fn test_payment_record_without_refund_address_refunds_customer() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 235);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+
+    // Drop the field to simulate an escrow written before refund addresses.
+    env.as_contract(&contract_id, || {
+        let key = DataKey::Payment(payment_id.clone());
+        let mut raw: Map<Symbol, Val> = env.storage().persistent().get(&key).unwrap();
+        raw.remove(symbol_short!("refund_to"));
+        env.storage().persistent().set(&key, &raw);
+    });
+
+    assert_eq!(client.get_refund_address(&payment_id), customer);
+    env.ledger().with_mut(|l| l.sequence_number = 111);
+    client.expire(&payment_id);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&customer), 1_000_000_000);
+}
+
+// ---------------------------------------------------------------------------
+// Split payouts
+// ---------------------------------------------------------------------------
+
+fn split_input(recipient: &Address, share: SplitShare) -> SplitRecipientInput {
+    SplitRecipientInput {
+        recipient: recipient.clone(),
+        share,
+    }
+}
+
+fn deposit_split_default_ttl(
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    payment_id: &BytesN<32>,
+    merchant: &Address,
+    amount: i128,
+    recipients: &Vec<SplitRecipientInput>,
+) {
+    client.deposit_split(
+        customer,
+        payment_id,
+        merchant,
+        &amount,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        recipients,
+    );
+}
+
+#[test]
+fn test_split_release_pays_every_recipient() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let platform = Address::generate(&env);
+    let courier = Address::generate(&env);
+    let payment_id = make_id(&env, 70);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(9_000)),
+        split_input(&platform, SplitShare::Bps(1_000)),
+        split_input(&courier, SplitShare::Fixed(10_000_000)),
+    ];
+    deposit_split_default_ttl(
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        110_000_000,
+        &recipients,
+    );
+
+    let split = client.get_split(&payment_id).unwrap();
+    assert_eq!(split.get(0).unwrap().amount, 90_000_000);
+    assert_eq!(split.get(1).unwrap().amount, 10_000_000);
+    assert_eq!(split.get(2).unwrap().amount, 10_000_000);
+
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 90_000_000);
+    assert_eq!(token_client.balance(&platform), 10_000_000);
+    assert_eq!(token_client.balance(&courier), 10_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_split_partial_release_is_pro_rata() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let platform = Address::generate(&env);
+    let payment_id = make_id(&env, 71);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(6_667)),
+        split_input(&platform, SplitShare::Bps(3_333)),
+    ];
+    deposit_split_default_ttl(&client, &customer, &payment_id, &merchant, 1_000, &recipients);
+
+    let token_client = token::Client::new(&env, &usdc);
+    client.release_partial(&admin, &payment_id, &100i128);
+    assert_eq!(token_client.balance(&merchant), 67);
+    assert_eq!(token_client.balance(&platform), 33);
+
+    client.release_partial(&admin, &payment_id, &1i128);
+    assert_eq!(
+        token_client.balance(&merchant) + token_client.balance(&platform),
+        101
+    );
+
+    client.release(&admin, &payment_id);
+    let split = client.get_split(&payment_id).unwrap();
+    assert_eq!(token_client.balance(&merchant), split.get(0).unwrap().amount);
+    assert_eq!(token_client.balance(&platform), split.get(1).unwrap().amount);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_split_dispute_resolved_to_merchant_pays_recipients() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let platform = Address::generate(&env);
+    let payment_id = make_id(&env, 72);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Fixed(80_000_000)),
+        split_input(&platform, SplitShare::Fixed(20_000_000)),
+    ];
+    deposit_split_default_ttl(
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        100_000_000,
+        &recipients,
+    );
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+    client.resolve_dispute(&admin, &payment_id, &merchant);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 80_000_000);
+    assert_eq!(token_client.balance(&platform), 20_000_000);
+}
+
+#[test]
+fn test_split_refund_returns_funds_to_customer() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let platform = Address::generate(&env);
+    let payment_id = make_id(&env, 73);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(5_000)),
+        split_input(&platform, SplitShare::Bps(5_000)),
+    ];
+    deposit_split_default_ttl(
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        100_000_000,
+        &recipients,
+    );
+    env.ledger().set_sequence_number(111);
+    client.refund(&payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+    assert_eq!(token_client.balance(&platform), 0);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_split_rejects_bps_not_totalling_100_percent() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let platform = Address::generate(&env);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(9_000)),
+        split_input(&platform, SplitShare::Bps(500)),
+    ];
+    assert_eq!(
+        client.try_deposit_split(
+            &customer,
+            &make_id(&env, 74),
+            &merchant,
+            &100_000_000,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &recipients,
+        ),
+        Err(Ok(EscrowError::InvalidSplit))
+    );
+}
+
+#[test]
+fn test_split_rejects_fixed_amounts_not_matching_deposit() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let platform = Address::generate(&env);
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Fixed(60_000_000)),
+        split_input(&platform, SplitShare::Fixed(30_000_000)),
+    ];
+    assert_eq!(
+        client.try_deposit_split(
+            &customer,
+            &make_id(&env, 75),
+            &merchant,
+            &100_000_000,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &recipients,
+        ),
+        Err(Ok(EscrowError::InvalidSplit))
+    );
+}
+
+#[test]
+fn test_split_rejects_duplicate_recipient() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(5_000)),
+        split_input(&merchant, SplitShare::Bps(5_000)),
+    ];
+    assert_eq!(
+        client.try_deposit_split(
+            &customer,
+            &make_id(&env, 76),
+            &merchant,
+            &100_000_000,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &recipients,
+        ),
+        Err(Ok(EscrowError::InvalidSplit))
+    );
+}
+
+#[test]
+fn test_split_rejects_empty_recipient_list() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+
+    assert_eq!(
+        client.try_deposit_split(
+            &customer,
+            &make_id(&env, 77),
+            &merchant,
+            &100_000_000,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &Vec::new(&env),
+        ),
+        Err(Ok(EscrowError::InvalidSplit))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Milestones
+// ---------------------------------------------------------------------------
+
+fn milestone(env: &Env, label: &str, amount: i128, deadline: u32) -> MilestoneInput {
+    MilestoneInput {
+        label: String::from_str(env, label),
+        amount,
+        deadline,
+    }
+}
+
+/// Deposit a three-milestone service contract (design, build, launch) with
+/// deadlines at ledgers 40, 70 and 100.
+fn deposit_service_contract(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    payment_id: &BytesN<32>,
+    merchant: &Address,
+    on_deadline: MilestoneDefault,
+) {
+    client.deposit_milestones(
+        customer,
+        payment_id,
+        merchant,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &vec![
+            env,
+            milestone(env, "design", 20_000_000, 40),
+            milestone(env, "build", 50_000_000, 70),
+            milestone(env, "launch", 30_000_000, 100),
+        ],
+        &on_deadline,
+    );
+}
+
+#[test]
+fn test_milestones_approved_one_by_one() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 80);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Refund,
+    );
+
+    assert_eq!(client.get_payment(&payment_id).amount, 100_000_000);
+    let token_client = token::Client::new(&env, &usdc);
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    assert_eq!(token_client.balance(&merchant), 20_000_000);
+    assert_eq!(
+        client.get_milestone(&payment_id, &0).status,
+        MilestoneStatus::Released
+    );
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Pending);
+
+    client.approve_milestone(&admin, &payment_id, &2);
+    client.approve_milestone(&customer, &payment_id, &1);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Released);
+    assert_eq!(payment.released_amount, 100_000_000);
+    assert_eq!(token_client.balance(&merchant), 100_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_milestone_deadline_defaults_to_release() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 81);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    env.ledger().set_sequence_number(41);
+    client.settle_milestone(&payment_id, &0);
+
+    assert_eq!(
+        client.get_milestone(&payment_id, &0).status,
+        MilestoneStatus::Released
+    );
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&merchant),
+        20_000_000
+    );
+}
+
+#[test]
+fn test_milestone_deadline_defaults_to_refund() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 82);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Refund,
+    );
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    env.ledger().set_sequence_number(101);
+    client.settle_milestone(&payment_id, &1);
+    client.settle_milestone(&payment_id, &2);
+
+    let plan = client.get_milestones(&payment_id);
+    assert_eq!(plan.on_deadline, MilestoneDefault::Refund);
+    assert_eq!(plan.milestones.get(1).unwrap().status, MilestoneStatus::Refunded);
+    assert_eq!(plan.milestones.get(2).unwrap().status, MilestoneStatus::Refunded);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 20_000_000);
+    assert_eq!(token_client.balance(&customer), 980_000_000);
+}
+
+#[test]
+fn test_settle_milestone_before_deadline() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 83);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    env.ledger().set_sequence_number(40);
+    assert_eq!(
+        client.try_settle_milestone(&payment_id, &0),
+        Err(Ok(EscrowError::MilestoneDeadlineNotReached))
+    );
+}
+
+#[test]
+fn test_milestone_cannot_be_approved_twice() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 84);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    assert_eq!(
+        client.try_approve_milestone(&customer, &payment_id, &0),
+        Err(Ok(EscrowError::MilestoneAlreadySettled))
+    );
+}
+
+#[test]
+fn test_milestone_approval_by_merchant_rejected() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 85);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    assert_eq!(
+        client.try_approve_milestone(&merchant, &payment_id, &0),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+}
+
+#[test]
+fn test_release_partial_blocked_for_milestone_escrow() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 86);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &20_000_000i128),
+        Err(Ok(EscrowError::ReleasedByMilestone))
+    );
+}
+
+#[test]
+fn test_milestone_deadline_after_expiry_rejected() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+
+    assert_eq!(
+        client.try_deposit_milestones(
+            &customer,
+            &make_id(&env, 87),
+            &merchant,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &vec![&env, milestone(&env, "late", 10_000_000, 111)],
+            &MilestoneDefault::Release,
+        ),
+        Err(Ok(EscrowError::InvalidMilestones))
+    );
+}
+
+#[test]
+fn test_refund_closes_open_milestones() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 88);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Refund,
+    );
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    env.ledger().set_sequence_number(111);
+    client.refund(&payment_id);
+
+    let plan = client.get_milestones(&payment_id);
+    assert_eq!(plan.milestones.get(1).unwrap().status, MilestoneStatus::Refunded);
+    assert_eq!(plan.milestones.get(2).unwrap().status, MilestoneStatus::Refunded);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&customer),
+        980_000_000
+    );
+}
+
+#[test]
+fn test_expiry_applies_release_default_to_due_milestones() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 89);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    // Expiry and the last deadline pass together; expiring first must not
+    // take the merchant's default release away.
+    env.ledger().set_sequence_number(111);
+    client.expire(&payment_id);
+
+    let plan = client.get_milestones(&payment_id);
+    assert_eq!(plan.milestones.get(1).unwrap().status, MilestoneStatus::Released);
+    assert_eq!(plan.milestones.get(2).unwrap().status, MilestoneStatus::Released);
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 100_000_000);
+    assert_eq!(token_client.balance(&customer), 900_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+// ---------------------------------------------------------------------------
+// Customer release / merchant refund
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_customer_release_pays_merchant() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 100);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.release_partial(&admin, &payment_id, &50_000_000i128);
+    client.customer_release(&customer, &payment_id);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Released);
+    assert_eq!(payment.released_amount, 250_000_000);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 250_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_customer_release_by_merchant_rejected() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 101);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    assert_eq!(
+        client.try_customer_release(&merchant, &payment_id),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+}
+
+#[test]
+fn test_customer_release_blocked_while_disputed() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 102);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.dispute(&merchant, &payment_id, &String::from_str(&env, "hold"));
+    assert_eq!(
+        client.try_customer_release(&customer, &payment_id),
+        Err(Ok(EscrowError::DisputeOpen))
+    );
+}
+
+#[test]
+fn test_merchant_partial_then_full_refund() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 103);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.merchant_refund(&merchant, &payment_id, &50_000_000i128);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Pending);
+    assert_eq!(client.get_balance(&payment_id), 200_000_000);
+
+    client.merchant_refund(&merchant, &payment_id, &200_000_000i128);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Expired);
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_merchant_refund_over_remaining() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 104);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    client.release_partial(&admin, &payment_id, &100_000_000i128);
+    assert_eq!(
+        client.try_merchant_refund(&merchant, &payment_id, &150_000_001i128),
+        Err(Ok(EscrowError::InvalidAmount))
+    );
+}
+
+#[test]
+fn test_merchant_refund_by_customer_rejected() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 105);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 250_000_000i128);
+    assert_eq!(
+        client.try_merchant_refund(&customer, &payment_id, &1i128),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+}
+
+#[test]
+fn test_merchant_partial_refund_blocked_for_milestone_escrow() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 106);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    assert_eq!(
+        client.try_merchant_refund(&merchant, &payment_id, &10_000_000i128),
+        Err(Ok(EscrowError::InvalidAmount))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Split dispute resolution
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_resolve_dispute_split_pays_both_sides() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 110);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 200_000_000i128);
+    client.release_partial(&admin, &payment_id, &100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "partly broken"));
+    client.resolve_dispute_split(&admin, &payment_id, &70_000_000i128, &30_000_000i128);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Resolved);
+    assert_eq!(payment.released_amount, 200_000_000);
+    assert_eq!(client.get_balance(&payment_id), 0);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 170_000_000);
+    assert_eq!(token_client.balance(&customer), 830_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_resolve_dispute_split_allows_zero_side() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 111);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&merchant, &payment_id, &String::from_str(&env, "cancel"));
+    client.resolve_dispute_split(&admin, &payment_id, &0i128, &100_000_000i128);
+
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Resolved);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&customer),
+        1_000_000_000
+    );
+}
+
+#[test]
+fn test_resolve_dispute_split_amounts_must_match_remaining() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 112);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "split"));
+    assert_eq!(
+        client.try_resolve_dispute_split(&admin, &payment_id, &70_000_000i128, &20_000_000i128),
+        Err(Ok(EscrowError::ResolutionMismatch))
+    );
+}
+
+#[test]
+fn test_resolve_dispute_split_requires_open_dispute() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 113);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    assert_eq!(
+        client.try_resolve_dispute_split(&admin, &payment_id, &70_000_000i128, &30_000_000i128),
+        Err(Ok(EscrowError::DisputeNotOpen))
+    );
+}
+
+#[test]
+fn test_release_after_split_resolution_rejected() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 114);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "split"));
+    client.resolve_dispute_split(&admin, &payment_id, &70_000_000i128, &30_000_000i128);
+    assert_eq!(client.try_release(&admin, &payment_id), Err(Ok(EscrowError::PaymentNotPending)));
+}
+
+// ---------------------------------------------------------------------------
+// Arbiters and evidence
+// ---------------------------------------------------------------------------
+
+fn evidence_hash(env: &Env, seed: u8) -> BytesN<32> {
+    BytesN::from_array(env, &[seed; 32])
+}
+
+#[test]
+fn test_arbiter_resolves_dispute() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let arbiter = Address::generate(&env);
+    let payment_id = make_id(&env, 120);
+
+    client.add_arbiter(&admin, &arbiter);
+    assert_eq!(client.get_arbiters(), vec![&env, arbiter.clone()]);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "not delivered"));
+    client.resolve_dispute(&arbiter, &payment_id, &customer);
+
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Expired);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&customer),
+        1_000_000_000
+    );
+}
+
+#[test]
+fn test_admin_cannot_resolve_once_arbiters_set() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 121);
+
+    client.add_arbiter(&admin, &Address::generate(&env));
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+    assert_eq!(
+        client.try_resolve_dispute_split(&admin, &payment_id, &50_000_000i128, &50_000_000i128),
+        Err(Ok(EscrowError::MissingRole))
+    );
+}
+
+#[test]
+fn test_remove_arbiter_restores_admin_fallback() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let arbiter = Address::generate(&env);
+    let payment_id = make_id(&env, 122);
+
+    client.add_arbiter(&admin, &arbiter);
+    client.remove_arbiter(&admin, &arbiter);
+    assert_eq!(client.get_arbiters().len(), 0);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+    client.resolve_dispute(&admin, &payment_id, &merchant);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_add_duplicate_arbiter_rejected() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    let arbiter = Address::generate(&env);
+    client.add_arbiter(&admin, &arbiter);
+    assert_eq!(
+        client.try_add_arbiter(&admin, &arbiter),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+}
+
+#[test]
+fn test_evidence_log_is_paginated() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 123);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "damaged"));
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    client.submit_evidence(&customer, &payment_id, &evidence_hash(&env, 1));
+    env.ledger().with_mut(|l| l.timestamp = 2_000);
+    client.submit_evidence(&merchant, &payment_id, &evidence_hash(&env, 2));
+    client.submit_evidence(&customer, &payment_id, &evidence_hash(&env, 3));
+
+    assert_eq!(client.get_evidence_count(&payment_id), 3);
+
+    let first = client.get_evidence(&payment_id, &0, &2);
+    assert_eq!(first.len(), 2);
+    let entry = first.get(0).unwrap();
+    assert_eq!(entry.hash, evidence_hash(&env, 1));
+    assert_eq!(entry.submitter, customer);
+    assert_eq!(entry.timestamp, 1_000);
+    assert_eq!(first.get(1).unwrap().submitter, merchant);
+
+    let rest = client.get_evidence(&payment_id, &2, &2);
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest.get(0).unwrap().hash, evidence_hash(&env, 3));
+    assert_eq!(rest.get(0).unwrap().timestamp, 2_000);
+
+    assert_eq!(client.get_evidence(&payment_id, &5, &2).len(), 0);
+}
+
+#[test]
+fn test_evidence_cap_is_per_party() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 126);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "damaged"));
+    for i in 0..25u8 {
+        client.submit_evidence(&customer, &payment_id, &evidence_hash(&env, i));
+    }
+    assert_eq!(
+        client.try_submit_evidence(&customer, &payment_id, &evidence_hash(&env, 25)),
+        Err(Ok(EscrowError::LimitExceeded))
+    );
+
+    // The merchant keeps its own share of the log.
+    client.submit_evidence(&merchant, &payment_id, &evidence_hash(&env, 26));
+    assert_eq!(client.get_evidence_count(&payment_id), 26);
+}
+
+#[test]
+fn test_evidence_requires_open_dispute() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 124);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    assert_eq!(
+        client.try_submit_evidence(&customer, &payment_id, &evidence_hash(&env, 1)),
+        Err(Ok(EscrowError::DisputeNotOpen))
+    );
+}
+
+#[test]
+fn test_evidence_rejects_outsider() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 125);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "damaged"));
+    assert_eq!(
+        client.try_submit_evidence(&Address::generate(&env), &payment_id, &evidence_hash(&env, 1)),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Dispute timeout
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_dispute_timeout_refunds_customer() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 130);
+
+    client.set_dispute_timeout_ledgers(&admin, &50);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&merchant, &payment_id, &String::from_str(&env, "no response"));
+    assert_eq!(client.get_dispute_deadline(&payment_id), Some(60));
+
+    env.ledger().with_mut(|l| l.sequence_number = 61);
+    client.timeout_dispute(&payment_id);
+
+    let event: DisputeTimeoutEvent = last_event(&env, &contract_id, "dispute_timeout");
+    assert_eq!(event.version, EVENT_SCHEMA_VERSION);
+    assert_eq!(event.deadline, 60);
+    assert_eq!(event.recipient, customer);
+    assert_eq!(event.amount, 100_000_000);
+    assert_eq!(event.prev_status, PaymentStatus::Disputed);
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.status, PaymentStatus::Expired);
+    assert_eq!(client.get_balance(&payment_id), 0);
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_dispute_timeout_before_deadline_rejected() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 131);
+
+    client.set_dispute_timeout_ledgers(&admin, &50);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+
+    env.ledger().with_mut(|l| l.sequence_number = 60);
+    assert_eq!(
+        client.try_timeout_dispute(&payment_id),
+        Err(Ok(EscrowError::DisputeDeadlineNotReached))
+    );
+}
+
+#[test]
+fn test_dispute_timeout_after_resolution_rejected() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 132);
+
+    client.set_dispute_timeout_ledgers(&admin, &50);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+    client.resolve_dispute(&admin, &payment_id, &merchant);
+
+    env.ledger().with_mut(|l| l.sequence_number = 61);
+    assert_eq!(client.try_timeout_dispute(&payment_id), Err(Ok(EscrowError::DisputeNotOpen)));
+}
+
+#[test]
+fn test_dispute_timeout_defaults_and_bounds() {
+    let (_env, client, _contract_id, admin, ..) = setup_env();
+    assert_eq!(client.get_dispute_timeout_ledgers(), 120_960);
+    assert_eq!(
+        client.try_set_dispute_timeout_ledgers(&admin, &0),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+    client.set_dispute_timeout_ledgers(&admin, &1_000);
+    assert_eq!(client.get_dispute_timeout_ledgers(), 1_000);
+}
+
+// ---------------------------------------------------------------------------
+// Batch entry points
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_release_batch_reports_skipped_items() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let ok_id = make_id(&env, 140);
+    let disputed_id = make_id(&env, 141);
+    let released_id = make_id(&env, 142);
+    let missing_id = make_id(&env, 143);
+
+    deposit_default_ttl(&client, &customer, &ok_id, &merchant, 10_000_000i128);
+    deposit_default_ttl(&client, &customer, &disputed_id, &merchant, 20_000_000i128);
+    deposit_default_ttl(&client, &customer, &released_id, &merchant, 30_000_000i128);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "late"));
+    client.release(&admin, &released_id);
+
+    let results = client.release_batch(
+        &admin,
+        &vec![&env, ok_id.clone(), disputed_id.clone(), released_id, missing_id],
+    );
+
+    assert_eq!(results.get(0).unwrap().error, None);
+    assert_eq!(results.get(1).unwrap().error, Some(EscrowError::DisputeOpen as u32));
+    assert_eq!(results.get(2).unwrap().error, Some(EscrowError::PaymentFullyReleased as u32));
+    assert_eq!(results.get(3).unwrap().error, Some(EscrowError::PaymentNotFound as u32));
+    assert_eq!(results.get(0).unwrap().amount, 10_000_000);
+    assert_eq!(results.get(1).unwrap().amount, 0);
+
+    assert_eq!(client.get_payment(&ok_id).status, PaymentStatus::Released);
+    assert_eq!(client.get_payment(&disputed_id).status, PaymentStatus::Disputed);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&merchant),
+        40_000_000
+    );
+}
+
+#[test]
+fn test_expire_and_refund_batch() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let expired_id = make_id(&env, 144);
+    let refunded_id = make_id(&env, 145);
+    let disputed_id = make_id(&env, 146);
+
+    deposit_default_ttl(&client, &customer, &expired_id, &merchant, 10_000_000i128);
+    deposit_default_ttl(&client, &customer, &refunded_id, &merchant, 20_000_000i128);
+    deposit_default_ttl(&client, &customer, &disputed_id, &merchant, 30_000_000i128);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "late"));
+
+    let early = client.expire_batch(&vec![&env, expired_id.clone()]);
+    assert_eq!(early.get(0).unwrap().error, Some(EscrowError::PaymentNotExpired as u32));
+
+    env.ledger().with_mut(|l| l.sequence_number = 111);
+    let expired = client.expire_batch(&vec![&env, expired_id.clone(), disputed_id.clone()]);
+    assert_eq!(expired.get(0).unwrap().error, None);
+    assert_eq!(expired.get(1).unwrap().error, Some(EscrowError::DisputeOpen as u32));
+
+    let refunded = client.refund_batch(&vec![&env, refunded_id.clone(), expired_id.clone()]);
+    assert_eq!(refunded.get(0).unwrap().error, None);
+    assert_eq!(refunded.get(0).unwrap().amount, 20_000_000);
+    assert_eq!(refunded.get(1).unwrap().error, Some(EscrowError::PaymentNotPending as u32));
+
+    assert_eq!(client.get_payment(&expired_id).status, PaymentStatus::Expired);
+    assert_eq!(client.get_payment(&refunded_id).status, PaymentStatus::Expired);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&customer),
+        970_000_000
+    );
+}
+
+#[test]
+fn test_batch_above_max_size_rejected() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    let mut ids = Vec::new(&env);
+    for seed in 0..=client.get_max_batch_size() {
+        ids.push_back(make_id(&env, 150 + seed as u8));
+    }
+    assert_eq!(client.try_release_batch(&admin, &ids), Err(Ok(EscrowError::LimitExceeded)));
+}
+
+#[test]
+fn test_release_batch_max_size_fits_network_limits() {
+    let (env, client, _contract_id, admin, customer, ..) = setup_env();
+    let max = client.get_max_batch_size();
+
+    // Every payment goes to its own merchant so each item touches fresh
+    // ledger entries.
+    let mut ids = Vec::new(&env);
+    for seed in 0..max {
+        let payment_id = make_id(&env, 170 + seed as u8);
+        let merchant = Address::generate(&env);
+        deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+        ids.push_back(payment_id);
+    }
+
+    env.cost_estimate().budget().reset_default();
+    let results = client.release_batch(&admin, &ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+
+    // Soroban per-transaction limits at the time of writing.
+    let resources = env.cost_estimate().resources();
+    assert!(resources.instructions <= 100_000_000);
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.write_bytes <= 132_096);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+// ---------------------------------------------------------------------------
+// Pause switches
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_global_deposit_pause_blocks_deposit() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+
+    client.pause(&admin, &PauseScope::Deposits);
+    assert!(client.is_paused(&PauseScope::Deposits));
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 190),
+            &merchant,
+            &1_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::DepositsPaused))
+    );
+}
+
+#[test]
+fn test_merchant_pause_only_blocks_that_merchant() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let other_merchant = Address::generate(&env);
+
+    client.pause(&admin, &PauseScope::Merchant(merchant.clone()));
+    deposit_default_ttl(&client, &customer, &make_id(&env, 191), &other_merchant, 1_000i128);
+
+    let blocked = client.try_deposit(
+        &customer,
+        &make_id(&env, 192),
+        &merchant,
+        &1_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
+    );
+    assert_eq!(blocked, Err(Ok(EscrowError::DepositsPaused)));
+
+    client.unpause(&admin, &PauseScope::Merchant(merchant.clone()));
+    deposit_default_ttl(&client, &customer, &make_id(&env, 192), &merchant, 1_000i128);
+}
+
+#[test]
+fn test_release_pause_keeps_refunds_open() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, _xlm, s1, ..) = setup_env();
+    let release_id = make_id(&env, 193);
+    let refund_id = make_id(&env, 194);
+
+    deposit_default_ttl(&client, &customer, &release_id, &merchant, 100_000_000i128);
+    deposit_default_ttl(&client, &customer, &refund_id, &merchant, 50_000_000i128);
+    client.pause(&s1, &PauseScope::Releases);
+
+    assert_eq!(
+        client.try_release(&admin, &release_id),
+        Err(Ok(EscrowError::ReleasesPaused))
+    );
+    assert_eq!(
+        client.try_customer_release(&customer, &release_id),
+        Err(Ok(EscrowError::ReleasesPaused))
+    );
+    client.merchant_refund(&merchant, &refund_id, &50_000_000i128);
+
+    client.unpause(&admin, &PauseScope::Releases);
+    client.release(&admin, &release_id);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&merchant),
+        100_000_000
+    );
+}
+
+#[test]
+fn test_release_pause_blocks_batch() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 195);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.pause(&admin, &PauseScope::Releases);
+    assert_eq!(
+        client.try_release_batch(&admin, &vec![&env, payment_id]),
+        Err(Ok(EscrowError::ReleasesPaused))
+    );
+}
+
+#[test]
+fn test_pause_rejects_outsider() {
+    let (env, client, ..) = setup_env();
+    assert_eq!(
+        client.try_pause(&Address::generate(&env), &PauseScope::Deposits),
+        Err(Ok(EscrowError::MissingRole))
+    );
+}
+
+#[test]
+fn test_emergency_signer_cannot_unpause() {
+    let (_env, client, _contract_id, _admin, _customer, _merchant, _usdc, _xlm, s1, ..) =
+        setup_env();
+    client.pause(&s1, &PauseScope::Deposits);
+    assert_eq!(client.try_unpause(&s1, &PauseScope::Deposits), Err(Ok(EscrowError::NotAdmin)));
+}
+
+// ---------------------------------------------------------------------------
+// Emergency signer rotation
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_rotate_emergency_signers_with_quorum() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, _xlm, s1, s2, s3, treasury) =
+        setup_env();
+    let new_one = Address::generate(&env);
+    let new_two = Address::generate(&env);
+    let new_signers = vec![&env, s3.clone(), new_one.clone(), new_two.clone()];
+
+    assert_eq!(client.get_emergency_threshold(), 2);
+    client.rotate_emergency_signers(&s1, &vec![&env, s1.clone(), s2.clone()], &new_signers, &3);
+    assert_eq!(client.get_emergency_signers(), new_signers);
+    assert_eq!(client.get_emergency_threshold(), 3);
+
+    deposit_default_ttl(&client, &customer, &make_id(&env, 200), &merchant, 1_000_000i128);
+    let assets = vec![&env, AssetType::Usdc];
+
+    let removed = client.try_emergency_drain(&s1, &vec![&env, s1.clone(), s3.clone()], &assets);
+    assert_eq!(removed, Err(Ok(EscrowError::MissingRole)));
+    let short = client.try_emergency_drain(&s3, &vec![&env, s3.clone(), new_one.clone()], &assets);
+    assert_eq!(short, Err(Ok(EscrowError::EmergencyQuorumNotMet)));
+
+    client.emergency_drain(&s3, &new_signers, &assets);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&treasury), 1_000_000);
+}
+
+#[test]
+fn test_rotate_emergency_signers_requires_quorum() {
+    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc, _xlm, s1, s2, ..) =
+        setup_env();
+    let new_signers = vec![&env, s1.clone(), s2.clone(), Address::generate(&env)];
+    assert_eq!(
+        client.try_rotate_emergency_signers(&s1, &vec![&env, s1.clone()], &new_signers, &2),
+        Err(Ok(EscrowError::EmergencyQuorumNotMet))
+    );
+}
+
+#[test]
+fn test_emergency_quorum_rejects_repeated_signer() {
+    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc, _xlm, s1, ..) =
+        setup_env();
+    assert_eq!(
+        client.try_emergency_drain(
+            &s1,
+            &vec![&env, s1.clone(), s1.clone()],
+            &vec![&env, AssetType::Usdc],
+        ),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+}
+
+#[test]
+fn test_rotate_emergency_signers_rejects_duplicates() {
+    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc, _xlm, s1, s2, ..) =
+        setup_env();
+    let new_signers = vec![&env, s1.clone(), s2.clone(), s2.clone()];
+    assert_eq!(
+        client.try_rotate_emergency_signers(
+            &s1,
+            &vec![&env, s1.clone(), s2.clone()],
+            &new_signers,
+            &2,
+        ),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+}
+
+#[test]
+fn test_rotate_emergency_signers_rejects_threshold_above_set() {
+    let (env, client, _contract_id, _admin, _customer, _merchant, _usdc, _xlm, s1, s2, ..) =
+        setup_env();
+    let new_signers = vec![&env, s1.clone(), s2.clone()];
+    assert_eq!(
+        client.try_rotate_emergency_signers(
+            &s1,
+            &vec![&env, s1.clone(), s2.clone()],
+            &new_signers,
+            &3,
+        ),
+        Err(Ok(EscrowError::InvalidConfig))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Roles and admin handover
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_roles_default_to_admin() {
+    let (_env, client, _contract_id, admin, ..) = setup_env();
+    assert_eq!(client.get_role(&Role::Upgrader), admin);
+    assert_eq!(client.get_role(&Role::Operator), admin);
+    assert_eq!(client.get_role(&Role::ConfigAdmin), admin);
+}
+
+#[test]
+fn test_operator_releases_instead_of_admin() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let operator = Address::generate(&env);
+    let payment_id = make_id(&env, 210);
+
+    client.set_role(&admin, &Role::Operator, &Some(operator.clone()));
+    assert_eq!(client.get_role(&Role::Operator), operator);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 100_000_000i128);
+
+    assert_eq!(
+        client.try_release(&admin, &payment_id),
+        Err(Ok(EscrowError::MissingRole))
+    );
+    client.release(&operator, &payment_id);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&merchant),
+        100_000_000
+    );
+}
+
+#[test]
+fn test_operator_cannot_upgrade() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    let operator = Address::generate(&env);
+    let upgrader = Address::generate(&env);
+
+    client.set_role(&admin, &Role::Operator, &Some(operator.clone()));
+    client.set_role(&admin, &Role::Upgrader, &Some(upgrader));
+    assert_eq!(
+        client.try_upgrade(&operator, &BytesN::from_array(&env, &[0u8; 32])),
+        Err(Ok(EscrowError::MissingRole))
+    );
+}
+
+#[test]
+fn test_config_admin_manages_token_limits() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    let config_admin = Address::generate(&env);
+    let token = Address::generate(&env);
+
+    client.set_role(&admin, &Role::ConfigAdmin, &Some(config_admin.clone()));
+    assert_eq!(
+        client.try_set_token_limits(&admin, &token, &1, &10),
+        Err(Ok(EscrowError::MissingRole))
+    );
+    client.set_token_limits(&config_admin, &token, &1, &10);
+    assert!(client.get_token_limits(&token).is_some());
+
+    client.set_role(&admin, &Role::ConfigAdmin, &None);
+    client.remove_token(&admin, &token);
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    let new_admin = Address::generate(&env);
+
+    client.propose_admin(&admin, &new_admin);
+    assert_eq!(client.get_admin(), admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+
+    client.accept_admin(&new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+    assert_eq!(
+        client.try_set_role(&admin, &Role::Operator, &Some(admin.clone())),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+    client.set_role(&new_admin, &Role::Operator, &Some(admin.clone()));
+}
+
+#[test]
+fn test_accept_admin_requires_proposal() {
+    let (env, client, _contract_id, admin, ..) = setup_env();
+    client.propose_admin(&admin, &Address::generate(&env));
+    assert_eq!(
+        client.try_accept_admin(&Address::generate(&env)),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+}
+
+#[test]
+fn test_set_role_admin_only() {
+    let (env, client, ..) = setup_env();
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        client.try_set_role(&outsider, &Role::Operator, &Some(outsider.clone())),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Storage TTL
+// ---------------------------------------------------------------------------
+
+const PAYMENT_TTL_GRACE_LEDGERS: u32 = 120_960;
+const CONFIG_TTL_EXTEND_TO: u32 = 535_680;
+
+fn payment_ttl(env: &Env, contract_id: &Address, payment_id: &BytesN<32>) -> u32 {
+    env.as_contract(contract_id, || {
+        env.storage()
+            .persistent()
+            .get_ttl(&DataKey::Payment(payment_id.clone()))
+    })
+}
+
+fn instance_ttl(env: &Env, contract_id: &Address) -> u32 {
+    env.as_contract(contract_id, || env.storage().instance().get_ttl())
+}
+
+fn advance_ledger(env: &Env, ledgers: u32) {
+    env.ledger().with_mut(|l| l.sequence_number += ledgers);
+}
+
+#[test]
+fn test_deposit_extends_payment_ttl_past_expiry() {
+    let (env, client, contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 220);
+
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &1_000i128,
+        &400_000,
+        &AssetType::Usdc,
+        &None,
+    );
+    assert_eq!(
+        payment_ttl(&env, &contract_id, &payment_id),
+        400_000 + PAYMENT_TTL_GRACE_LEDGERS
+    );
+    assert_eq!(instance_ttl(&env, &contract_id), CONFIG_TTL_EXTEND_TO);
+}
+
+#[test]
+fn test_refund_after_long_escrow_outlives_default_ttl() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 221);
+
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &100_000_000i128,
+        &400_000,
+        &AssetType::Usdc,
+        &None,
+    );
+    // The token contract keeps its own instance alive for only a week.
+    env.as_contract(&usdc, || env.storage().instance().extend_ttl(500_000, 500_000));
+
+    // Far past the minimum persistent TTL the entries started with.
+    advance_ledger(&env, 400_001);
+    assert!(payment_ttl(&env, &contract_id, &payment_id) > 0);
+    client.refund(&payment_id);
+
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Expired);
+    assert_eq!(
+        token::Client::new(&env, &usdc).balance(&customer),
+        1_000_000_000
+    );
+}
+
+#[test]
+fn test_dispute_extends_ttl_to_dispute_deadline() {
+    let (env, client, contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 222);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+
+    let deadline = client.get_dispute_deadline(&payment_id).unwrap();
+    let expected = deadline - env.ledger().sequence() + PAYMENT_TTL_GRACE_LEDGERS;
+    assert_eq!(payment_ttl(&env, &contract_id, &payment_id), expected);
+    let deadline_ttl = env.as_contract(&contract_id, || {
+        env.storage()
+            .persistent()
+            .get_ttl(&DataKey::DisputeDeadline(payment_id.clone()))
+    });
+    assert_eq!(deadline_ttl, expected);
+}
+
+#[test]
+fn test_bump_payment_restores_ttl() {
+    let (env, client, contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 223);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    advance_ledger(&env, 100_000);
+    let before = payment_ttl(&env, &contract_id, &payment_id);
+
+    client.bump_payment(&payment_id);
+    let after = payment_ttl(&env, &contract_id, &payment_id);
+    assert!(after > before);
+    assert_eq!(after, PAYMENT_TTL_GRACE_LEDGERS);
+}
+
+#[test]
+fn test_keep_alive_extends_instance_past_archival() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 224);
+
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &1_000i128,
+        &400_000,
+        &AssetType::Usdc,
+        &None,
+    );
+
+    // Without the keeper the instance would be archived before the second
+    // advance completes.
+    advance_ledger(&env, 450_000);
+    client.keep_alive(&vec![&env, payment_id.clone(), make_id(&env, 225)]);
+    assert_eq!(instance_ttl(&env, &contract_id), CONFIG_TTL_EXTEND_TO);
+    assert_eq!(payment_ttl(&env, &contract_id, &payment_id), PAYMENT_TTL_GRACE_LEDGERS);
+
+    advance_ledger(&env, 450_000);
+    assert_eq!(client.get_admin(), admin);
+}
+
+#[test]
+fn test_keep_alive_keeps_token_limits_and_pause_flags_past_config_ttl() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    client.set_token_limits(&admin, &usdc, &1_000i128, &5_000i128);
+    client.pause(&admin, &PauseScope::Releases);
+
+    // Each advance alone outlasts a config entry that was only extended
+    // when it was written.
+    for _ in 0..2 {
+        advance_ledger(&env, CONFIG_TTL_EXTEND_TO - 1_000);
+        client.keep_alive(&Vec::new(&env));
+    }
+    assert_eq!(instance_ttl(&env, &contract_id), CONFIG_TTL_EXTEND_TO);
+
+    assert!(client.is_paused(&PauseScope::Releases));
+    assert_eq!(
+        client.try_deposit(
+            &customer,
+            &make_id(&env, 226),
+            &merchant,
+            &10_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::AmountOutOfRange))
+    );
+    assert_eq!(
+        client.get_token_limits(&usdc),
+        Some(TokenLimits {
+            min_amount: 1_000,
+            max_amount: 5_000,
+        })
+    );
+}
+
+#[test]
+fn test_deposit_from_sponsor_funds_escrow_and_receives_refund() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let sponsor = Address::generate(&env);
+    let payment_id = make_id(&env, 230);
+    token::StellarAssetClient::new(&env, &usdc).mint(&sponsor, &100_000_000i128);
+
+    client.deposit_from(
+        &sponsor,
+        &customer,
+        &payment_id,
+        &merchant,
+        &100_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
+    );
+    let auths = env.auths();
+    assert_eq!(auths.len(), 1);
+    assert_eq!(auths[0].0, sponsor);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&sponsor), 0);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+    assert_eq!(token_client.balance(&contract_id), 100_000_000);
+    assert_eq!(client.get_payment(&payment_id).customer, customer);
+    assert_eq!(client.get_refund_address(&payment_id), sponsor);
+
+    env.ledger().with_mut(|l| l.sequence_number = 111);
+    client.expire(&payment_id);
+    assert_eq!(token_client.balance(&sponsor), 100_000_000);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+}
+
+#[test]
+fn test_deposit_from_refunds_go_to_configured_address() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let sponsor = Address::generate(&env);
+    let refund_to = Address::generate(&env);
+    let refunded_id = make_id(&env, 231);
+    let disputed_id = make_id(&env, 232);
+    token::StellarAssetClient::new(&env, &usdc).mint(&sponsor, &100_000_000i128);
+
+    for payment_id in [&refunded_id, &disputed_id] {
+        client.deposit_from(
+            &sponsor,
+            &customer,
+            payment_id,
+            &merchant,
+            &50_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &Some(refund_to.clone()),
+        );
+    }
+    assert_eq!(client.get_refund_address(&refunded_id), refund_to);
+
+    client.merchant_refund(&merchant, &refunded_id, &20_000_000i128);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "not delivered"));
+    client.resolve_dispute(&admin, &disputed_id, &customer);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&refund_to), 70_000_000);
+    assert_eq!(token_client.balance(&sponsor), 0);
+    assert_eq!(token_client.balance(&customer), 1_000_000_000);
+}
+
+#[test]
+fn test_direct_deposit_refunds_customer() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 233);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+    assert_eq!(client.get_payment(&payment_id).refund_to, None);
+    assert_eq!(client.get_refund_address(&payment_id), customer);
+    assert_eq!(
+        client.try_get_refund_address(&make_id(&env, 234)),
+        Err(Ok(EscrowError::PaymentNotFound))
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Auto-release
+// ---------------------------------------------------------------------------
+
+fn deposit_auto_release(
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    payment_id: &BytesN<32>,
+    merchant: &Address,
+    auto_release_after: u32,
+) {
+    client.deposit(
+        customer,
+        payment_id,
+        merchant,
+        &100_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &Some(auto_release_after),
+    );
+}
+
+#[test]
+fn test_claim_pays_merchant_after_auto_release_ledger() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 236);
+    deposit_auto_release(&client, &customer, &payment_id, &merchant, 60);
+    assert_eq!(client.get_payment(&payment_id).auto_release_after, Some(60));
+    assert_eq!(client.get_payment(&payment_id).dispute_window_end, 60);
+
+    env.ledger().with_mut(|l| l.sequence_number = 60);
+    assert_eq!(client.try_claim(&payment_id), Err(Ok(EscrowError::AutoReleaseNotDue)));
+
+    env.ledger().with_mut(|l| l.sequence_number = 61);
+    env.set_auths(&[]);
+    client.claim(&payment_id);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 100_000_000);
+}
+
+#[test]
+fn test_dispute_before_auto_release_blocks_claim() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let disputed_id = make_id(&env, 237);
+    let late_id = make_id(&env, 238);
+    deposit_auto_release(&client, &customer, &disputed_id, &merchant, 60);
+    deposit_auto_release(&client, &customer, &late_id, &merchant, 60);
+
+    env.ledger().with_mut(|l| l.sequence_number = 60);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "damaged"));
+
+    env.ledger().with_mut(|l| l.sequence_number = 61);
+    assert_eq!(client.try_claim(&disputed_id), Err(Ok(EscrowError::DisputeOpen)));
+    assert_eq!(
+        client.try_dispute(&customer, &late_id, &String::from_str(&env, "too late")),
+        Err(Ok(EscrowError::DisputeWindowExpired))
+    );
+}
+
+#[test]
+fn test_auto_release_bounds() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 239);
+
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+    env.ledger().with_mut(|l| l.sequence_number = 109);
+    assert_eq!(client.try_claim(&payment_id), Err(Ok(EscrowError::AutoReleaseNotDue)));
+
+    // Must fall strictly between the deposit ledger and expiry (109 + 100).
+    for ledger in [109, 209] {
+        assert_eq!(
+            client.try_deposit(
+                &customer,
+                &make_id(&env, 240),
+                &merchant,
+                &1_000_000i128,
+                &DEFAULT_PAYMENT_TTL,
+                &AssetType::Usdc,
+                &Some(ledger),
+            ),
+            Err(Ok(EscrowError::InvalidTtl))
+        );
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Indexes
+// ---------------------------------------------------------------------------
+
+#[test]
+fn test_merchant_and_customer_indexes_follow_status() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let other_customer = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&other_customer, &1_000_000i128);
+
+    deposit_default_ttl(&client, &customer, &make_id(&env, 1), &merchant, 1_000i128);
+    deposit_default_ttl(&client, &other_customer, &make_id(&env, 2), &merchant, 1_000i128);
+    deposit_default_ttl(&client, &customer, &make_id(&env, 3), &merchant, 1_000i128);
+    client.release(&admin, &make_id(&env, 1));
+
+    let all = client.get_merchant_payments(&merchant, &None, &0, &10);
+    assert_eq!(all.payments.len(), 3);
+    assert_eq!(all.next, None);
+
+    let open = client.get_merchant_payments(&merchant, &Some(PaymentStatus::Pending), &0, &10);
+    assert_eq!(open.payments.len(), 2);
+    assert_eq!(open.payments.get(0).unwrap().payment_id, make_id(&env, 2));
+    assert_eq!(open.payments.get(1).unwrap().payment_id, make_id(&env, 3));
+
+    let mine = client.get_customer_payments(&customer, &Some(PaymentStatus::Pending), &0, &10);
+    assert_eq!(mine.payments.len(), 1);
+    assert_eq!(mine.payments.get(0).unwrap().payment_id, make_id(&env, 3));
+    assert_eq!(client.get_index_len(&EscrowIndex::Customer(customer)), 2);
+    assert_eq!(client.get_index_len(&EscrowIndex::Customer(other_customer)), 1);
+}
+
+#[test]
+fn test_index_pages_across_storage_pages() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    for seed in 0..70u8 {
+        deposit_default_ttl(&client, &customer, &make_id(&env, seed), &merchant, 1_000i128);
+    }
+    assert_eq!(client.get_index_len(&EscrowIndex::Merchant(merchant.clone())), 70);
+
+    // Query size is capped to stay inside the read-entry limit, and pages
+    // continue where the last one stopped.
+    env.cost_estimate().budget().reset_default();
+    let first = client.get_merchant_payments(&merchant, &None, &50, &100);
+    assert!(env.cost_estimate().resources().read_entries <= 40);
+    assert_eq!(first.payments.len(), 20);
+    assert_eq!(first.payments.get(0).unwrap().payment_id, make_id(&env, 50));
+    assert_eq!(first.payments.get(19).unwrap().payment_id, make_id(&env, 69));
+    assert_eq!(first.next, None);
+
+    let page = client.get_merchant_payments(&merchant, &None, &0, &20);
+    assert_eq!(page.next, Some(20));
+    assert_eq!(client.get_merchant_payments(&merchant, &None, &70, &20).payments.len(), 0);
+}
+
+#[test]
+fn test_expired_index_lists_pending_past_expiry() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let expired_id = make_id(&env, 241);
+    let disputed_id = make_id(&env, 242);
+    deposit_default_ttl(&client, &customer, &expired_id, &merchant, 1_000i128);
+    deposit_default_ttl(&client, &customer, &disputed_id, &merchant, 1_000i128);
+    client.dispute(&customer, &disputed_id, &String::from_str(&env, "late"));
+
+    let expiry = client.get_expiry(&expired_id);
+    let bucket = client.get_expiry_bucket(&expiry);
+    assert_eq!(client.get_expired_payments(&bucket, &0, &20).payments.len(), 0);
+
+    env.ledger().with_mut(|l| l.sequence_number = expiry + 1);
+    let due = client.get_expired_payments(&bucket, &0, &20);
+    assert_eq!(due.payments.len(), 1);
+    assert_eq!(due.payments.get(0).unwrap().payment_id, expired_id);
+
+    client.expire(&expired_id);
+    assert_eq!(client.get_expired_payments(&bucket, &0, &20).payments.len(), 0);
+}
+
+// ---------------------------------------------------------------------------
+// Fee hooks
+// ---------------------------------------------------------------------------
+
+/// Register a 1% fee calculator and a distributor sending 30% of fees to the
+/// LP, wire both into the escrow, and return (treasury, lp).
+fn setup_fee_hooks(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    admin: &Address,
+    usdc: &Address,
+) -> (Address, Address) {
+    let treasury = Address::generate(env);
+    let lp = Address::generate(env);
+    let tiers = vec![
+        env,
+        FeeTier {
+            threshold_usdc: 0,
+            fee_bps: 100,
+        },
+    ];
+    let calculator = env.register(FeeCalculatorContract, (admin, tiers));
+    let distributor =
+        env.register(FeeDistributorContract, (admin, &treasury, &lp, &3_000i128, usdc));
+    client.set_fee_hooks(
+        admin,
+        &Some(FeeHooks {
+            calculator,
+            distributor,
+        }),
+    );
+    (treasury, lp)
+}
+
+#[test]
+fn test_release_with_fee_hooks_pays_net_and_distributes_fee() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (treasury, lp) = setup_fee_hooks(&env, &client, &admin, &usdc);
+    let payment_id = make_id(&env, 243);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 990_000);
+    assert_eq!(token_client.balance(&treasury), 7_000);
+    assert_eq!(token_client.balance(&lp), 3_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_partial_release_with_fee_hooks_charges_each_payout() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (treasury, lp) = setup_fee_hooks(&env, &client, &admin, &usdc);
+    let payment_id = make_id(&env, 244);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+
+    client.release_partial(&admin, &payment_id, &400_000i128);
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&merchant), 990_000);
+    assert_eq!(token_client.balance(&treasury) + token_client.balance(&lp), 10_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).released_amount, 1_000_000);
+}
+
+#[test]
+fn test_fee_hooks_skip_non_usdc_escrows() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, xlm, ..) = setup_env();
+    let (treasury, _lp) = setup_fee_hooks(&env, &client, &admin, &usdc);
+    let payment_id = make_id(&env, 245);
+    client.deposit(
+        &customer,
+        &payment_id,
+        &merchant,
+        &1_000_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Xlm,
+        &None,
+    );
+
+    client.release(&admin, &payment_id);
+
+    assert_eq!(token::Client::new(&env, &xlm).balance(&merchant), 1_000_000);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&treasury), 0);
+    assert_eq!(token::Client::new(&env, &xlm).balance(&contract_id), 0);
+}
+
+#[test]
+fn test_split_release_with_fee_hooks_shares_fee_pro_rata() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (treasury, lp) = setup_fee_hooks(&env, &client, &admin, &usdc);
+    let platform = Address::generate(&env);
+    let payment_id = make_id(&env, 246);
+    let recipients = vec![
+        &env,
+        split_input(&merchant, SplitShare::Bps(7_500)),
+        split_input(&platform, SplitShare::Bps(2_500)),
+    ];
+    deposit_split_default_ttl(
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        1_000_003,
+        &recipients,
+    );
+
+    client.release(&admin, &payment_id);
+
+    let token_client = token::Client::new(&env, &usdc);
+    let fee = token_client.balance(&treasury) + token_client.balance(&lp);
+    assert_eq!(fee, 10_000);
+    assert_eq!(token_client.balance(&merchant), 742_503);
+    assert_eq!(token_client.balance(&platform), 247_500);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    let split = client.get_split(&payment_id).unwrap();
+    assert_eq!(split.get(0).unwrap().released, split.get(0).unwrap().amount);
+    assert_eq!(split.get(1).unwrap().released, split.get(1).unwrap().amount);
+}
+
+#[test]
+fn test_set_fee_hooks_requires_config_admin() {
+    let (env, client, _contract_id, admin, customer, _merchant, usdc, ..) = setup_env();
+    assert_eq!(client.get_fee_hooks(), None);
+
+    let hooks = FeeHooks {
+        calculator: Address::generate(&env),
+        distributor: Address::generate(&env),
+    };
+    assert_eq!(
+        client.try_set_fee_hooks(&customer, &Some(hooks.clone())),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+
+    setup_fee_hooks(&env, &client, &admin, &usdc);
+    assert!(client.get_fee_hooks().is_some());
+    client.set_fee_hooks(&admin, &None);
+    assert_eq!(client.get_fee_hooks(), None);
+}
+
+#[test]
+fn test_release_batch_with_fee_hooks_fits_network_limits() {
+    let (env, client, _contract_id, admin, customer, _merchant, usdc, ..) = setup_env();
+    setup_fee_hooks(&env, &client, &admin, &usdc);
+    let max = client.get_max_batch_size();
+    assert_eq!(max, 3);
+
+    let mut ids = Vec::new(&env);
+    for seed in 0..max {
+        let payment_id = make_id(&env, 247 + seed as u8);
+        let merchant = Address::generate(&env);
+        deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+        ids.push_back(payment_id);
+    }
+
+    env.cost_estimate().budget().reset_default();
+    let results = client.release_batch(&admin, &ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+
+    // Same per-transaction limits as the hook-free batch test.
+    let resources = env.cost_estimate().resources();
+    assert!(resources.instructions <= 100_000_000);
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+// ---------------------------------------------------------------------------
+// Settlement ledger
+// ---------------------------------------------------------------------------
+
+/// Register a settlement ledger that accepts records from the escrow.
+fn setup_settlement_ledger(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    contract_id: &Address,
+    admin: &Address,
+) -> SettlementLedgerContractClient<'static> {
+    let ledger_id = env.register(SettlementLedgerContract, (admin,));
+    let ledger = SettlementLedgerContractClient::new(env, &ledger_id);
+    ledger.set_writer(admin, contract_id, &true);
+    client.set_settlement_ledger(admin, &Some(ledger_id));
+    ledger
+}
+
+#[test]
+fn test_release_records_settlement() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    setup_fee_hooks(&env, &client, &admin, &usdc);
+    let payment_id = make_id(&env, 1);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+
+    client.release(&admin, &payment_id);
+
+    let record = ledger.get_settlement(&payment_id);
+    assert_eq!(record.merchant, merchant);
+    assert_eq!(record.amount, 1_000_000);
+    assert_eq!(record.fee, 10_000);
+    assert_eq!(record.net, 990_000);
+    assert_eq!(ledger.settlement_count(&merchant), 1);
+}
+
+#[test]
+fn test_partial_releases_record_one_settlement_each() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let payment_id = make_id(&env, 2);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+
+    client.release_partial(&admin, &payment_id, &300i128);
+    client.release_partial(&admin, &payment_id, &200i128);
+    client.release(&admin, &payment_id);
+
+    assert_eq!(ledger.settlement_count(&merchant), 3);
+    assert_eq!(ledger.get_settlement(&payment_id).amount, 300);
+    let second = client.get_settlement_id(&payment_id, &300i128);
+    assert_eq!(ledger.get_settlement(&second).amount, 200);
+    let last = client.get_settlement_id(&payment_id, &500i128);
+    assert_eq!(ledger.get_settlement(&last).amount, 500);
+}
+
+#[test]
+fn test_resolve_dispute_records_settlement_only_for_merchant_win() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let arbiter = Address::generate(&env);
+    client.add_arbiter(&admin, &arbiter);
+    let merchant_win = make_id(&env, 3);
+    let customer_win = make_id(&env, 4);
+    deposit_default_ttl(&client, &customer, &merchant_win, &merchant, 1_000i128);
+    deposit_default_ttl(&client, &customer, &customer_win, &merchant, 1_000i128);
+    client.dispute(&customer, &merchant_win, &String::from_str(&env, "late"));
+    client.dispute(&customer, &customer_win, &String::from_str(&env, "late"));
+
+    client.resolve_dispute(&arbiter, &merchant_win, &merchant);
+    client.resolve_dispute(&arbiter, &customer_win, &customer);
+
+    assert_eq!(ledger.get_settlement(&merchant_win).net, 1_000);
+    assert!(ledger.try_get_settlement(&customer_win).is_err());
+    assert_eq!(ledger.settlement_count(&merchant), 1);
+}
+
+#[test]
+fn test_resolve_dispute_split_records_merchant_share() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let payment_id = make_id(&env, 6);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "late"));
+
+    client.resolve_dispute_split(&admin, &payment_id, &700i128, &300i128);
+
+    assert_eq!(ledger.get_settlement(&payment_id).amount, 700);
+    assert_eq!(ledger.settlement_count(&merchant), 1);
+}
+
+#[test]
+fn test_customer_release_records_settlement() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let payment_id = make_id(&env, 7);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.release_partial(&admin, &payment_id, &400i128);
+
+    client.customer_release(&customer, &payment_id);
+
+    let rest = client.get_settlement_id(&payment_id, &400i128);
+    assert_eq!(ledger.get_settlement(&rest).amount, 600);
+    assert_eq!(ledger.settlement_count(&merchant), 2);
+}
+
+#[test]
+fn test_milestone_payouts_record_settlements() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let payment_id = make_id(&env, 8);
+    deposit_service_contract(
+        &env,
+        &client,
+        &customer,
+        &payment_id,
+        &merchant,
+        MilestoneDefault::Release,
+    );
+
+    client.approve_milestone(&customer, &payment_id, &0);
+    env.ledger().set_sequence_number(71);
+    client.settle_milestone(&payment_id, &1);
+
+    assert_eq!(ledger.get_settlement(&payment_id).amount, 20_000_000);
+    let build = client.get_settlement_id(&payment_id, &20_000_000i128);
+    assert_eq!(ledger.get_settlement(&build).amount, 50_000_000);
+    assert_eq!(ledger.settlement_count(&merchant), 2);
+}
+
+#[test]
+fn test_release_fails_when_escrow_is_not_a_ledger_writer() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    ledger.set_writer(&admin, &contract_id, &false);
+    let payment_id = make_id(&env, 5);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+
+    assert!(client.try_release(&admin, &payment_id).is_err());
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Pending);
+}
+
+#[test]
+fn test_set_settlement_ledger_requires_config_admin() {
+    let (env, client, _contract_id, admin, customer, ..) = setup_env();
+    let ledger = Address::generate(&env);
+    assert_eq!(
+        client.try_set_settlement_ledger(&customer, &Some(ledger.clone())),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+
+    client.set_settlement_ledger(&admin, &Some(ledger.clone()));
+    assert_eq!(client.get_settlement_ledger(), Some(ledger));
+    client.set_settlement_ledger(&admin, &None);
+    assert_eq!(client.get_settlement_ledger(), None);
+}
+
+#[test]
+fn test_release_batch_with_settlement_ledger_fits_network_limits() {
+    let (env, client, contract_id, admin, customer, ..) = setup_env();
+    setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    let max = client.get_max_batch_size();
+    assert_eq!(max, 4);
+
+    let mut ids = Vec::new(&env);
+    for seed in 0..max {
+        let payment_id = make_id(&env, 10 + seed as u8);
+        let merchant = Address::generate(&env);
+        deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+        ids.push_back(payment_id);
+    }
+    env.cost_estimate().budget().reset_default();
+    let results = client.release_batch(&admin, &ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+
+    let resources = env.cost_estimate().resources();
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+#[test]
+fn test_release_batch_with_fees_and_settlement_ledger_fits_network_limits() {
+    let (env, client, contract_id, admin, customer, _merchant, usdc, ..) = setup_env();
+    setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    setup_fee_hooks(&env, &client, &admin, &usdc);
+    let max = client.get_max_batch_size();
+    assert_eq!(max, 3);
+
+    let mut ids = Vec::new(&env);
+    for seed in 0..max {
+        let payment_id = make_id(&env, 20 + seed as u8);
+        let merchant = Address::generate(&env);
+        deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000_000i128);
+        ids.push_back(payment_id);
+    }
+    env.cost_estimate().budget().reset_default();
+    let results = client.release_batch(&admin, &ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+
+    let resources = env.cost_estimate().resources();
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+/// Fill a `release_batch` to the escrow's current cap, each payment to its
+/// own registered and KYC-verified merchant, and check it fits the network
+/// limits.
+fn assert_kyc_batch_fits_network_limits(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    registry: &MerchantRegistryContractClient<'_>,
+    admin: &Address,
+    customer: &Address,
+) {
+    let mut ids = Vec::new(env);
+    for seed in 0..client.get_max_batch_size() {
+        let payment_id = make_id(env, 30 + seed as u8);
+        let merchant = Address::generate(env);
+        registry.register_merchant(admin, &merchant, &String::from_str(env, "Merchant"));
+        registry.set_kyc_status(admin, &merchant, &true);
+        client.deposit(
+            customer,
+            &payment_id,
+            &merchant,
+            &1_000_000i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        );
+        ids.push_back(payment_id);
+    }
+    env.cost_estimate().budget().reset_default();
+    let results = client.release_batch(admin, &ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+
+    let resources = env.cost_estimate().resources();
+    assert!(resources.instructions <= 100_000_000);
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+#[test]
+fn test_release_batch_with_registry_fits_network_limits() {
+    let (env, client, registry, admin, customer, ..) = setup_with_registry();
+    assert_eq!(client.get_max_batch_size(), 6);
+    assert_kyc_batch_fits_network_limits(&env, &client, &registry, &admin, &customer);
+}
+
+#[test]
+fn test_release_batch_with_registry_and_settlement_ledger_fits_network_limits() {
+    let (env, client, registry, admin, customer, ..) = setup_with_registry();
+    setup_settlement_ledger(&env, &client, &client.address, &admin);
+    assert_eq!(client.get_max_batch_size(), 3);
+    assert_kyc_batch_fits_network_limits(&env, &client, &registry, &admin, &customer);
+}
+
+#[test]
+fn test_release_batch_with_registry_fees_and_settlement_ledger_fits_network_limits() {
+    let (env, client, registry, admin, customer, _merchant, usdc, ..) = setup_with_registry();
+    setup_settlement_ledger(&env, &client, &client.address, &admin);
+    setup_fee_hooks(&env, &client, &admin, &usdc);
+    assert_eq!(client.get_max_batch_size(), 2);
+    assert_kyc_batch_fits_network_limits(&env, &client, &registry, &admin, &customer);
+}
+
+// ---------------------------------------------------------------------------
+// Event schema
+// ---------------------------------------------------------------------------
+
+/// Topic and payload of the payment transitions the last call published.
+fn escrow_events(env: &Env, contract_id: &Address) -> Vec<(String, EscrowEvent)> {
+    let mut events = Vec::new(env);
+    for (contract, topics, data) in env.events().all().iter() {
+        if contract != *contract_id {
+            continue;
+        }
+        let topic = topics.get(1).and_then(|t| String::try_from_val(env, &t).ok());
+        if let (Some(topic), Ok(event)) = (topic, EscrowEvent::try_from_val(env, &data)) {
+            events.push_back((topic, event));
+        }
+    }
+    events
+}
+
+/// Payload of the `topic` event the last call published.
+fn last_event<T: TryFromVal<Env, Val>>(env: &Env, contract_id: &Address, topic: &str) -> T {
+    let topic = String::from_str(env, topic);
+    let (_, _, data) = env
+        .events()
+        .all()
+        .iter()
+        .filter(|(contract, topics, _)| {
+            *contract == *contract_id
+                && topics
+                    .get(1)
+                    .and_then(|t| String::try_from_val(env, &t).ok())
+                    .is_some_and(|t| t == topic)
+        })
+        .last()
+        .unwrap();
+    T::try_from_val(env, &data).ok().unwrap()
+}
+
+#[test]
+fn test_release_event_describes_transition() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let payment_id = make_id(&env, 30);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+
+    let deposit = escrow_events(&env, &contract_id);
+    assert_eq!(deposit.len(), 1);
+    assert_eq!(deposit.get(0).unwrap().0, String::from_str(&env, "deposit"));
+    assert_eq!(deposit.get(0).unwrap().1.recipient, None);
+    assert_eq!(deposit.get(0).unwrap().1.status, PaymentStatus::Pending);
+
+    client.release_partial(&admin, &payment_id, &400i128);
+    client.release(&admin, &payment_id);
+
+    let events = escrow_events(&env, &contract_id);
+    assert_eq!(events.len(), 1);
+    let (topic, event) = events.get(0).unwrap();
+    assert_eq!(topic, String::from_str(&env, "release"));
+    assert_eq!(event.version, EVENT_SCHEMA_VERSION);
+    assert_eq!(event.payment_id, payment_id);
+    assert_eq!(event.asset, usdc);
+    assert_eq!(event.customer, customer);
+    assert_eq!(event.recipient, Some(merchant));
+    assert_eq!(event.amount, 600);
+    assert_eq!(event.prev_status, PaymentStatus::Pending);
+    assert_eq!(event.status, PaymentStatus::Released);
+    assert_eq!(event.released_amount, 1_000);
+    assert_eq!(event.milestone, None);
+}
+
+#[test]
+fn test_expire_event_names_refund_recipient() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let sponsor = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&sponsor, &1_000i128);
+    let payment_id = make_id(&env, 31);
+    client.deposit_from(
+        &sponsor,
+        &customer,
+        &payment_id,
+        &merchant,
+        &1_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &None,
+    );
+    let expiry = client.get_expiry(&payment_id);
+    env.ledger().with_mut(|l| l.sequence_number = expiry + 1);
+
+    client.expire(&payment_id);
+
+    let events = escrow_events(&env, &contract_id);
+    let (topic, event) = events.get(0).unwrap();
+    assert_eq!(topic, String::from_str(&env, "expired"));
+    assert_eq!(event.recipient, Some(sponsor));
+    assert_eq!(event.asset, usdc);
+    assert_eq!(event.status, PaymentStatus::Expired);
+}
+
+#[test]
+fn test_dispute_events_carry_reason_and_own_topic() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let arbiter = Address::generate(&env);
+    client.add_arbiter(&admin, &arbiter);
+    let payment_id = make_id(&env, 32);
+    let reason = String::from_str(&env, "item not delivered");
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+
+    client.dispute(&customer, &payment_id, &reason);
+    let events = escrow_events(&env, &contract_id);
+    assert_eq!(events.get(0).unwrap().0, String::from_str(&env, "dispute"));
+    assert_eq!(events.get(0).unwrap().1.recipient, None);
+    assert_eq!(events.get(0).unwrap().1.dispute_reason, Some(reason.clone()));
+
+    client.resolve_dispute(&arbiter, &payment_id, &customer);
+    let event: DisputeResolvedEvent = last_event(&env, &contract_id, "dispute_resolved");
+    assert_eq!(event.refund_to, customer);
+    assert_eq!(event.merchant_amount, 0);
+    assert_eq!(event.customer_amount, 1_000);
+    assert_eq!(event.prev_status, PaymentStatus::Disputed);
+    assert_eq!(event.status, PaymentStatus::Expired);
+    assert_eq!(event.dispute_reason, Some(reason));
+}
+
+#[test]
+fn test_split_resolution_emits_one_event_with_both_amounts() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let arbiter = Address::generate(&env);
+    client.add_arbiter(&admin, &arbiter);
+    let payment_id = make_id(&env, 33);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.dispute(&merchant, &payment_id, &String::from_str(&env, "partial"));
+
+    client.resolve_dispute_split(&arbiter, &payment_id, &700i128, &300i128);
+
+    let resolved = env.events().all().iter().filter(|(_, topics, _)| {
+        topics.get(1).and_then(|t| String::try_from_val(&env, &t).ok())
+            == Some(String::from_str(&env, "dispute_resolved"))
+    });
+    assert_eq!(resolved.count(), 1);
+    let event: DisputeResolvedEvent = last_event(&env, &contract_id, "dispute_resolved");
+    assert_eq!(event.version, EVENT_SCHEMA_VERSION);
+    assert_eq!(event.merchant, merchant);
+    assert_eq!(event.refund_to, customer);
+    assert_eq!(event.merchant_amount, 700);
+    assert_eq!(event.customer_amount, 300);
+    assert_eq!(event.status, PaymentStatus::Resolved);
+    assert_eq!(event.released_amount, 1_000);
+}
+
+#[test]
+fn test_one_sided_split_resolution_still_emits_event() {
+    let (env, client, contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = make_id(&env, 34);
+    deposit_default_ttl(&client, &customer, &payment_id, &merchant, 1_000i128);
+    client.dispute(&customer, &payment_id, &String::from_str(&env, "refund"));
+
+    client.resolve_dispute_split(&admin, &payment_id, &0i128, &1_000i128);
+
+    let event: DisputeResolvedEvent = last_event(&env, &contract_id, "dispute_resolved");
+    assert_eq!(event.merchant_amount, 0);
+    assert_eq!(event.customer_amount, 1_000);
+}
+
+
+// ---------------------------------------------------------------------------
+// Invoice-bound deposits
+// ---------------------------------------------------------------------------
+
+const INVOICE_EXPIRY: u64 = 1_000;
+
+/// Register a payment request contract with one pending USDC invoice of
+/// `amount` for `merchant`, and point the escrow at it as a confirmer.
+fn setup_invoice(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    admin: &Address,
+    merchant: &Address,
+    usdc: &Address,
+    amount: i128,
+) -> (PaymentRequestContractClient<'static>, String) {
+    let requests_id = env.register(PaymentRequestContract, (admin,));
+    let requests = PaymentRequestContractClient::new(env, &requests_id);
+    requests.set_confirmer(admin, &client.address, &true);
+    let invoice_id = String::from_str(env, "inv_001");
+    requests.create_payment(&invoice_id, merchant, &amount, usdc, &INVOICE_EXPIRY);
+    client.set_invoice_contract(admin, &Some(requests_id));
+    (requests, invoice_id)
+}
+
+fn deposit_invoice(
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    payment_id: &BytesN<32>,
+    invoice_id: &String,
+    merchant: &Address,
+    amount: i128,
+    asset_type: &AssetType,
+) -> Result<BytesN<32>, EscrowError> {
+    match client.try_deposit_invoice(
+        customer,
+        payment_id,
+        invoice_id,
+        merchant,
+        &amount,
+        &DEFAULT_PAYMENT_TTL,
+        asset_type,
+    ) {
+        Ok(Ok(payment_id)) => Ok(payment_id),
+        Err(Ok(error)) => Err(error),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_deposit_invoice_escrows_and_confirms_request() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 40);
+
+    deposit_invoice(
+        &client,
+        &customer,
+        &payment_id,
+        &invoice_id,
+        &merchant,
+        5_000,
+        &AssetType::Usdc,
+    )
+    .unwrap();
+
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.invoice_id, Some(invoice_id.clone()));
+    assert_eq!(payment.amount, 5_000);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 5_000);
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Confirmed);
+}
+
+#[test]
+fn test_deposit_invoice_rejects_under_and_overpayment() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 41);
+
+    for amount in [4_999i128, 5_001] {
+        assert_eq!(
+            deposit_invoice(
+                &client,
+                &customer,
+                &payment_id,
+                &invoice_id,
+                &merchant,
+                amount,
+                &AssetType::Usdc,
+            ),
+            Err(EscrowError::InvoiceMismatch)
+        );
+    }
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 0);
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Pending);
+}
+
+#[test]
+fn test_deposit_invoice_checks_merchant_and_asset() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (_requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 42);
+
+    assert_eq!(
+        deposit_invoice(
+            &client,
+            &customer,
+            &payment_id,
+            &invoice_id,
+            &Address::generate(&env),
+            5_000,
+            &AssetType::Usdc,
+        ),
+        Err(EscrowError::InvoiceMismatch)
+    );
+    assert_eq!(
+        deposit_invoice(
+            &client,
+            &customer,
+            &payment_id,
+            &invoice_id,
+            &merchant,
+            5_000,
+            &AssetType::Xlm,
+        ),
+        Err(EscrowError::InvoiceMismatch)
+    );
+}
+
+#[test]
+fn test_deposit_invoice_requires_pending_unexpired_request() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (_requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let pay = |seed: u8, invoice_id: &String| {
+        deposit_invoice(
+            &client,
+            &customer,
+            &make_id(&env, seed),
+            invoice_id,
+            &merchant,
+            5_000,
+            &AssetType::Usdc,
+        )
+    };
+
+    assert_eq!(
+        pay(43, &String::from_str(&env, "inv_unknown")),
+        Err(EscrowError::InvoiceNotFound)
+    );
+    pay(44, &invoice_id).unwrap();
+    assert_eq!(pay(45, &invoice_id), Err(EscrowError::InvoiceNotPayable));
+
+    let (_requests, late_invoice) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    env.ledger().set_timestamp(INVOICE_EXPIRY + 1);
+    assert_eq!(pay(46, &late_invoice), Err(EscrowError::InvoiceNotPayable));
+}
+
+#[test]
+fn test_deposit_invoice_requires_escrow_to_be_confirmer() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    requests.set_confirmer(&admin, &contract_id, &false);
+
+    let result = client.try_deposit_invoice(
+        &customer,
+        &make_id(&env, 48),
+        &invoice_id,
+        &merchant,
+        &5_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+    );
+    assert!(result.is_err());
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Pending);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 0);
+}
+
+#[test]
+fn test_refunded_invoice_escrow_fails_request() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 49);
+    deposit_invoice(
+        &client,
+        &customer,
+        &payment_id,
+        &invoice_id,
+        &merchant,
+        5_000,
+        &AssetType::Usdc,
+    )
+    .unwrap();
+
+    env.ledger().set_sequence_number(client.get_expiry(&payment_id) + 1);
+    client.expire(&payment_id);
+
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Failed);
+}
+
+#[test]
+fn test_released_invoice_escrow_keeps_request_confirmed() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 50);
+    deposit_invoice(
+        &client,
+        &customer,
+        &payment_id,
+        &invoice_id,
+        &merchant,
+        5_000,
+        &AssetType::Usdc,
+    )
+    .unwrap();
+
+    client.release(&admin, &payment_id);
+
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Confirmed);
+}
+
+#[test]
+fn test_refund_of_settled_invoice_escrow_still_succeeds() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, invoice_id) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let payment_id = make_id(&env, 51);
+    deposit_invoice(
+        &client,
+        &customer,
+        &payment_id,
+        &invoice_id,
+        &merchant,
+        5_000,
+        &AssetType::Usdc,
+    )
+    .unwrap();
+    requests.settle(&invoice_id);
+
+    client.merchant_refund(&merchant, &payment_id, &5_000i128);
+
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Expired);
+    assert_eq!(requests.get_payment(&invoice_id).status, InvoiceStatus::Settled);
+}
+
+#[test]
+fn test_expire_batch_of_invoice_escrows_fits_network_limits() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let (requests, _) = setup_invoice(&env, &client, &admin, &merchant, &usdc, 5_000);
+    let invoices = ["inv_a", "inv_b", "inv_c", "inv_d", "inv_e", "inv_f", "inv_g", "inv_h"];
+    assert_eq!(invoices.len() as u32, client.get_max_batch_size());
+
+    // Each escrow pays its own merchant's invoice, so every item also fails a
+    // separate request in the request contract.
+    let mut ids = Vec::new(&env);
+    for (seed, invoice) in invoices.iter().enumerate() {
+        let invoice_id = String::from_str(&env, invoice);
+        let merchant = Address::generate(&env);
+        requests.create_payment(&invoice_id, &merchant, &5_000i128, &usdc, &INVOICE_EXPIRY);
+        let payment_id = make_id(&env, 60 + seed as u8);
+        deposit_invoice(
+            &client,
+            &customer,
+            &payment_id,
+            &invoice_id,
+            &merchant,
+            5_000,
+            &AssetType::Usdc,
+        )
+        .unwrap();
+        ids.push_back(payment_id);
+    }
+
+    env.ledger().set_sequence_number(client.get_expiry(&ids.get(0).unwrap()) + 1);
+    env.cost_estimate().budget().reset_default();
+    let results = client.expire_batch(&ids);
+    assert!(results.iter().all(|r| r.error.is_none()));
+    let last = String::from_str(&env, invoices[invoices.len() - 1]);
+    assert_eq!(requests.get_payment(&last).status, InvoiceStatus::Failed);
+
+    let resources = env.cost_estimate().resources();
+    assert!(resources.instructions <= 100_000_000);
+    assert!(resources.read_entries + resources.write_entries <= 40);
+    assert!(resources.write_entries <= 25);
+    assert!(resources.write_bytes <= 132_096);
+    assert!(resources.contract_events_size_bytes <= 8_198);
+}
+
+#[test]
+fn test_deposit_invoice_requires_configured_contract() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let invoice_id = String::from_str(&env, "inv_001");
+    assert_eq!(
+        deposit_invoice(
+            &client,
+            &customer,
+            &make_id(&env, 47),
+            &invoice_id,
+            &merchant,
+            5_000,
+            &AssetType::Usdc,
+        ),
+        Err(EscrowError::InvalidConfig)
+    );
+
+    let requests = Address::generate(&env);
+    assert_eq!(
+        client.try_set_invoice_contract(&customer, &Some(requests.clone())),
+        Err(Ok(EscrowError::NotAdmin))
+    );
+    client.set_invoice_contract(&admin, &Some(requests.clone()));
+    assert_eq!(client.get_invoice_contract(), Some(requests));
+}
+
+// ---------------------------------------------------------------------------
+// Subscriptions
+// ---------------------------------------------------------------------------
+
+const PERIOD_LEDGERS: u32 = 100;
+
+/// Subscribe `customer` to pay `merchant` 250 USDC per period for
+/// `periods` periods starting now.
+fn subscribe(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    merchant: &Address,
+    seed: u8,
+    periods: u32,
+) -> BytesN<32> {
+    let subscription_id = make_id(env, seed);
+    client.subscribe(
+        customer,
+        &subscription_id,
+        merchant,
+        &250i128,
+        &PERIOD_LEDGERS,
+        &(env.ledger().sequence() + periods * PERIOD_LEDGERS),
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+    );
+    subscription_id
+}
+
+#[test]
+fn test_subscription_charges_each_due_period_into_new_escrow() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let subscription_id = subscribe(&env, &client, &customer, &merchant, 50, 12);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 3_000);
+
+    let first = client.charge(&subscription_id);
+    assert_eq!(first, client.get_subscription_payment_id(&subscription_id, &0));
+    let payment = client.get_payment(&first);
+    assert_eq!(payment.customer, customer);
+    assert_eq!(payment.merchant, merchant);
+    assert_eq!(payment.amount, 250);
+    assert_eq!(payment.status, PaymentStatus::Pending);
+    assert_eq!(token_client.balance(&contract_id), 250);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 2_750);
+
+    assert_eq!(client.try_charge(&subscription_id), Err(Ok(EscrowError::SubscriptionNotDue)));
+
+    env.ledger().with_mut(|l| l.sequence_number += PERIOD_LEDGERS);
+    let second = client.charge(&subscription_id);
+    assert_eq!(second, client.get_subscription_payment_id(&subscription_id, &1));
+    assert_eq!(token_client.balance(&contract_id), 500);
+
+    let subscription = client.get_subscription(&subscription_id);
+    assert_eq!(subscription.periods_charged, 2);
+    assert_eq!(subscription.status, SubscriptionStatus::Active);
+}
+
+#[test]
+fn test_subscription_ends_after_last_period() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let subscription_id = subscribe(&env, &client, &customer, &merchant, 51, 3);
+
+    // Periods missed while nobody called `charge` can still be collected.
+    env.ledger().with_mut(|l| l.sequence_number += 5 * PERIOD_LEDGERS);
+    for _ in 0..3 {
+        client.charge(&subscription_id);
+    }
+
+    assert_eq!(client.get_subscription(&subscription_id).status, SubscriptionStatus::Ended);
+    assert_eq!(
+        client.try_charge(&subscription_id),
+        Err(Ok(EscrowError::SubscriptionNotActive))
+    );
+    let token_client = token::Client::new(&env, &usdc);
+    assert_eq!(token_client.balance(&contract_id), 750);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 0);
+}
+
+#[test]
+fn test_charge_is_permissionless() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let subscription_id = subscribe(&env, &client, &customer, &merchant, 52, 2);
+
+    env.set_auths(&[]);
+    client.charge(&subscription_id);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 250);
+}
+
+#[test]
+fn test_cancel_subscription_withdraws_uncharged_allowance() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let kept = subscribe(&env, &client, &customer, &merchant, 53, 4);
+    let cancelled = subscribe(&env, &client, &customer, &merchant, 54, 4);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 2_000);
+    let charged = client.charge(&cancelled);
+
+    assert_eq!(
+        client.try_cancel_subscription(&merchant, &cancelled),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+    client.cancel_subscription(&customer, &cancelled);
+
+    let events = env.events().all();
+    let (_, topics, _) = events.get(events.len() - 1).unwrap();
+    assert_eq!(
+        String::try_from_val(&env, &topics.get(1).unwrap()).unwrap(),
+        String::from_str(&env, "sub_cancelled")
+    );
+    assert_eq!(client.get_subscription(&cancelled).status, SubscriptionStatus::Cancelled);
+    assert_eq!(client.try_charge(&cancelled), Err(Ok(EscrowError::SubscriptionNotActive)));
+    assert_eq!(
+        client.try_cancel_subscription(&customer, &cancelled),
+        Err(Ok(EscrowError::SubscriptionNotActive))
+    );
+
+    // The other subscription's allowance and the charged escrow are untouched.
+    assert_eq!(token_client.allowance(&customer, &contract_id), 1_000);
+    assert_eq!(client.get_payment(&charged).status, PaymentStatus::Pending);
+    client.charge(&kept);
+}
+
+#[test]
+fn test_cancel_subscription_keeps_due_periods_chargeable() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let subscription_id = subscribe(&env, &client, &customer, &merchant, 55, 6);
+    client.charge(&subscription_id);
+
+    // Periods 1 and 2 are due but nobody has charged them yet.
+    env.ledger().with_mut(|l| l.sequence_number += 2 * PERIOD_LEDGERS + 10);
+    client.cancel_subscription(&customer, &subscription_id);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 500);
+
+    client.charge(&subscription_id);
+    client.charge(&subscription_id);
+    assert_eq!(
+        client.try_charge(&subscription_id),
+        Err(Ok(EscrowError::SubscriptionNotActive))
+    );
+    let subscription = client.get_subscription(&subscription_id);
+    assert_eq!(subscription.periods_charged, 3);
+    assert_eq!(subscription.status, SubscriptionStatus::Cancelled);
+    assert_eq!(token_client.balance(&contract_id), 750);
+    assert_eq!(token_client.allowance(&customer, &contract_id), 0);
+}
+
+#[test]
+fn test_subscribe_validates_schedule() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let now = env.ledger().sequence();
+    let subscribe = |amount: i128, period: u32, end: u32| {
+        client.try_subscribe(
+            &customer,
+            &make_id(&env, 55),
+            &merchant,
+            &amount,
+            &period,
+            &end,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+        )
+    };
+
+    assert_eq!(subscribe(0, 10, now + 100), Err(Ok(EscrowError::InvalidAmount)));
+    assert_eq!(subscribe(250, 0, now + 100), Err(Ok(EscrowError::InvalidTtl)));
+    assert_eq!(subscribe(250, 10, now), Err(Ok(EscrowError::InvalidTtl)));
+    assert_eq!(subscribe(250, 10, u32::MAX), Err(Ok(EscrowError::InvalidTtl)));
+    subscribe(250, 10, now + 100).unwrap().unwrap();
+    assert_eq!(
+        subscribe(250, 10, now + 100),
+        Err(Ok(EscrowError::SubscriptionAlreadyExists))
+    );
+    assert_eq!(
+        client.try_get_subscription(&make_id(&env, 56)),
+        Err(Ok(EscrowError::SubscriptionNotFound))
+    );
+}
+
+#[test]
+fn test_deposit_cannot_squat_subscription_period_id() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let subscription_id = subscribe(&env, &client, &customer, &merchant, 57, 2);
+    let squatter = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&squatter, &1_000i128);
+
+    let period_id = client.get_subscription_payment_id(&subscription_id, &0);
+    assert_eq!(
+        client.try_deposit(
+            &squatter,
+            &period_id,
+            &merchant,
+            &1i128,
+            &DEFAULT_PAYMENT_TTL,
+            &AssetType::Usdc,
+            &None,
+        ),
+        Err(Ok(EscrowError::PaymentAlreadyExists))
+    );
+
+    assert_eq!(client.charge(&subscription_id), period_id);
+    assert_eq!(client.get_payment(&period_id).customer, customer);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&contract_id), 250);
+}
+
+// ---------------------------------------------------------------------------
+// Streams
+// ---------------------------------------------------------------------------
+
+const STREAM_TTL: u32 = 200;
+
+/// Stream 1 000 USDC to `merchant` over the 100 ledgers starting now.
+fn deposit_stream(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    merchant: &Address,
+    seed: u8,
+) -> BytesN<32> {
+    let start = env.ledger().sequence();
+    client.deposit_stream(
+        customer,
+        &make_id(env, seed),
+        merchant,
+        &1_000i128,
+        &STREAM_TTL,
+        &AssetType::Usdc,
+        &start,
+        &(start + 100),
+    )
+}
+
+#[test]
+fn test_stream_vests_linearly_for_withdraw() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 60);
+    assert_eq!(
+        client.try_withdraw_available(&merchant, &payment_id),
+        Err(Ok(EscrowError::StreamBalanceEmpty))
+    );
+
+    env.ledger().with_mut(|l| l.sequence_number += 25);
+    assert_eq!(client.get_withdrawable(&payment_id), 250);
+    assert_eq!(client.withdraw_available(&merchant, &payment_id), 250);
+    assert_eq!(token_client.balance(&merchant), 250);
+    assert_eq!(
+        client.try_withdraw_available(&merchant, &payment_id),
+        Err(Ok(EscrowError::StreamBalanceEmpty))
+    );
+
+    env.ledger().with_mut(|l| l.sequence_number += 30);
+    assert_eq!(client.withdraw_available(&merchant, &payment_id), 300);
+
+    // Past the end ledger everything has vested.
+    env.ledger().with_mut(|l| l.sequence_number += 90);
+    assert_eq!(client.withdraw_available(&merchant, &payment_id), 450);
+    assert_eq!(token_client.balance(&merchant), 1_000);
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.released_amount, 1_000);
+    assert_eq!(payment.status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_withdraw_available_requires_merchant_and_stream() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 61);
+    env.ledger().with_mut(|l| l.sequence_number += 50);
+    assert_eq!(
+        client.try_withdraw_available(&customer, &payment_id),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+
+    let lump_sum = make_id(&env, 62);
+    deposit_default_ttl(&client, &customer, &lump_sum, &merchant, 1_000i128);
+    assert_eq!(
+        client.try_withdraw_available(&merchant, &lump_sum),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+    assert_eq!(
+        client.try_cancel_stream(&customer, &lump_sum),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+}
+
+#[test]
+fn test_cancel_stream_refunds_unvested_and_leaves_vested_to_merchant() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let customer_start = token_client.balance(&customer);
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 63);
+
+    env.ledger().with_mut(|l| l.sequence_number += 40);
+    client.withdraw_available(&merchant, &payment_id);
+    env.ledger().with_mut(|l| l.sequence_number += 20);
+    assert_eq!(
+        client.try_cancel_stream(&merchant, &payment_id),
+        Err(Ok(EscrowError::NotPaymentParty))
+    );
+    client.cancel_stream(&customer, &payment_id);
+
+    let events = escrow_events(&env, &contract_id);
+    assert_eq!(events.len(), 1);
+    let (topic, cancelled) = events.get(0).unwrap();
+    assert_eq!(topic, String::from_str(&env, "stream_cancelled"));
+    assert_eq!(cancelled.recipient, Some(customer.clone()));
+    assert_eq!(cancelled.amount, 400);
+    assert_eq!(cancelled.status, PaymentStatus::Pending);
+    assert_eq!(token_client.balance(&customer), customer_start - 600);
+    assert_eq!(
+        client.try_cancel_stream(&customer, &payment_id),
+        Err(Ok(EscrowError::StreamBalanceEmpty))
+    );
+
+    // Vesting stopped at the cancel; the 200 vested since the last
+    // withdrawal stays claimable.
+    env.ledger().with_mut(|l| l.sequence_number += 20);
+    assert_eq!(client.get_withdrawable(&payment_id), 200);
+    assert_eq!(client.withdraw_available(&merchant, &payment_id), 200);
+    assert_eq!(token_client.balance(&merchant), 600);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_cancel_stream_before_vesting_expires_escrow() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let customer_start = token_client.balance(&customer);
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 66);
+
+    client.cancel_stream(&customer, &payment_id);
+    assert_eq!(token_client.balance(&customer), customer_start);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Expired);
+}
+
+#[test]
+fn test_cancel_stream_refunds_while_releases_paused() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let customer_start = token_client.balance(&customer);
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 67);
+
+    env.ledger().with_mut(|l| l.sequence_number += 30);
+    client.pause(&admin, &PauseScope::Releases);
+    client.cancel_stream(&customer, &payment_id);
+    assert_eq!(token_client.balance(&customer), customer_start - 300);
+    assert_eq!(
+        client.try_withdraw_available(&merchant, &payment_id),
+        Err(Ok(EscrowError::ReleasesPaused))
+    );
+
+    client.unpause(&admin, &PauseScope::Releases);
+    assert_eq!(client.withdraw_available(&merchant, &payment_id), 300);
+    assert_eq!(token_client.balance(&merchant), 300);
+}
+
+#[test]
+fn test_expired_stream_pays_unwithdrawn_vested_funds_to_merchant() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let customer_start = token_client.balance(&customer);
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 68);
+
+    env.ledger().with_mut(|l| l.sequence_number += 40);
+    client.withdraw_available(&merchant, &payment_id);
+    env.ledger().with_mut(|l| l.sequence_number += STREAM_TTL);
+    client.expire(&payment_id);
+
+    let events = escrow_events(&env, &contract_id);
+    assert_eq!(events.len(), 2);
+    let (topic, withdraw) = events.get(0).unwrap();
+    assert_eq!(topic, String::from_str(&env, "withdraw"));
+    assert_eq!(withdraw.recipient, Some(merchant.clone()));
+    assert_eq!(withdraw.amount, 600);
+    let (topic, expired) = events.get(1).unwrap();
+    assert_eq!(topic, String::from_str(&env, "expired"));
+    assert_eq!(expired.recipient, None);
+    assert_eq!(expired.amount, 0);
+
+    assert_eq!(token_client.balance(&merchant), 1_000);
+    assert_eq!(token_client.balance(&customer), customer_start - 1_000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_stream_is_not_released_as_lump_sum() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let payment_id = deposit_stream(&env, &client, &customer, &merchant, 64);
+
+    assert_eq!(
+        client.try_release(&admin, &payment_id),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+    assert_eq!(
+        client.try_release_partial(&admin, &payment_id, &100i128),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+}
+
+#[test]
+fn test_deposit_stream_validates_range() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let now = env.ledger().sequence();
+    let deposit = |start: u32, end: u32| {
+        client.try_deposit_stream(
+            &customer,
+            &make_id(&env, 65),
+            &merchant,
+            &1_000i128,
+            &STREAM_TTL,
+            &AssetType::Usdc,
+            &start,
+            &end,
+        )
+    };
+
+    assert_eq!(deposit(now - 1, now + 100), Err(Ok(EscrowError::InvalidTtl)));
+    assert_eq!(deposit(now + 50, now + 50), Err(Ok(EscrowError::InvalidTtl)));
+    assert_eq!(deposit(now, now + STREAM_TTL), Err(Ok(EscrowError::InvalidTtl)));
+
+    // A stream may start in the future; nothing vests before it does.
+    let payment_id = deposit(now + 50, now + 150).unwrap().unwrap();
+    env.ledger().with_mut(|l| l.sequence_number += 50);
+    assert_eq!(client.get_withdrawable(&payment_id), 0);
+    let payment = client.get_payment(&payment_id);
+    assert_eq!(payment.stream_start, Some(now + 50));
+    assert_eq!(payment.stream_end, Some(now + 150));
+}
+
+// ---------------------------------------------------------------------------
+// Hashlocked swaps
+// ---------------------------------------------------------------------------
+
+/// Lock 1 000 USDC behind the SHA-256 hashlock of `preimage`, timing out
+/// after the default TTL.
+fn deposit_htlc(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    merchant: &Address,
+    seed: u8,
+    preimage: &Bytes,
+) -> BytesN<32> {
+    client.deposit_htlc(
+        customer,
+        &make_id(env, seed),
+        merchant,
+        &1_000i128,
+        &env.crypto().sha256(preimage).into(),
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+    )
+}
+
+#[test]
+fn test_preimage_releases_htlc_to_merchant() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let preimage = Bytes::from_array(&env, &[7u8; 32]);
+    let payment_id = deposit_htlc(&env, &client, &customer, &merchant, 70, &preimage);
+
+    env.set_auths(&[]);
+    client.claim_with_preimage(&payment_id, &preimage);
+
+    // The release transition is published first, then the preimage.
+    let events = env.events().all();
+    let topic = |i: u32| {
+        let (_, topics, _) = events.get(events.len() - i).unwrap();
+        String::try_from_val(&env, &topics.get(1).unwrap()).unwrap()
+    };
+    assert_eq!(topic(2), String::from_str(&env, "release"));
+    assert_eq!(topic(1), String::from_str(&env, "preimage"));
+    let (contract, _, data) = events.get(events.len() - 1).unwrap();
+    assert_eq!(contract, contract_id);
+    let data: Map<Symbol, Val> = Map::try_from_val(&env, &data).unwrap();
+    let revealed = Bytes::try_from_val(&env, &data.get(Symbol::new(&env, "preimage")).unwrap());
+    assert_eq!(revealed.unwrap(), preimage);
+
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 1_000);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_htlc_refunds_customer_after_timeout() {
+    let (env, client, _contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let token_client = token::Client::new(&env, &usdc);
+    let customer_start = token_client.balance(&customer);
+    let preimage = Bytes::from_array(&env, &[8u8; 32]);
+    let payment_id = deposit_htlc(&env, &client, &customer, &merchant, 71, &preimage);
+
+    assert_eq!(
+        client.try_claim_with_preimage(&payment_id, &Bytes::from_array(&env, &[9u8; 32])),
+        Err(Ok(EscrowError::PreimageMismatch))
+    );
+    assert_eq!(client.try_refund(&payment_id), Err(Ok(EscrowError::PaymentNotExpired)));
+
+    env.ledger().with_mut(|l| l.sequence_number += DEFAULT_PAYMENT_TTL + 1);
+    assert_eq!(
+        client.try_claim_with_preimage(&payment_id, &preimage),
+        Err(Ok(EscrowError::PaymentExpired))
+    );
+    client.refund(&payment_id);
+    assert_eq!(token_client.balance(&customer), customer_start);
+    assert_eq!(token_client.balance(&merchant), 0);
+}
+
+#[test]
+fn test_htlc_is_not_disputed_or_released_by_operator() {
+    let (env, client, _contract_id, admin, customer, merchant, ..) = setup_env();
+    let preimage = Bytes::from_array(&env, &[10u8; 32]);
+    let payment_id = deposit_htlc(&env, &client, &customer, &merchant, 72, &preimage);
+
+    assert_eq!(
+        client.try_dispute(&customer, &payment_id, &String::from_str(&env, "no swap")),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+    assert_eq!(
+        client.try_release(&admin, &payment_id),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+
+    let plain = make_id(&env, 73);
+    deposit_default_ttl(&client, &customer, &plain, &merchant, 1_000i128);
+    assert_eq!(
+        client.try_claim_with_preimage(&plain, &preimage),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+}
+
+#[test]
+fn test_preimage_claim_completes_while_releases_paused() {
+    let (env, client, _contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let preimage = Bytes::from_array(&env, &[11u8; 32]);
+    let payment_id = deposit_htlc(&env, &client, &customer, &merchant, 74, &preimage);
+
+    client.pause(&admin, &PauseScope::Releases);
+    client.claim_with_preimage(&payment_id, &preimage);
+
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 1_000);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+    env.ledger().with_mut(|l| l.sequence_number += DEFAULT_PAYMENT_TTL + 1);
+    assert_eq!(client.try_refund(&payment_id), Err(Ok(EscrowError::PaymentFullyReleased)));
+}
+
+#[test]
+fn test_preimage_claim_completes_when_settlement_ledger_rejects_it() {
+    let (env, client, contract_id, admin, customer, merchant, usdc, ..) = setup_env();
+    let ledger = setup_settlement_ledger(&env, &client, &contract_id, &admin);
+    ledger.set_writer(&admin, &contract_id, &false);
+    let preimage = Bytes::from_array(&env, &[12u8; 32]);
+    let payment_id = deposit_htlc(&env, &client, &customer, &merchant, 75, &preimage);
+
+    client.claim_with_preimage(&payment_id, &preimage);
+
+    let events = env.events().all();
+    let skipped = events.iter().any(|(contract, topics, _)| {
+        contract == contract_id
+            && String::try_from_val(&env, &topics.get(1).unwrap()).ok()
+                == Some(String::from_str(&env, "settlement_skipped"))
+    });
+    assert!(skipped);
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 1_000);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+    assert_eq!(ledger.settlement_count(&merchant), 0);
+}
+
+// ---------------------------------------------------------------------------
+// Attested releases
+// ---------------------------------------------------------------------------
+
+/// Lock 1 000 USDC until `attester` attests to `condition_hash`.
+fn deposit_attested(
+    env: &Env,
+    client: &PaymentEscrowContractClient<'_>,
+    customer: &Address,
+    merchant: &Address,
+    seed: u8,
+    attester: &SigningKey,
+    condition_hash: &BytesN<32>,
+) -> BytesN<32> {
+    client.deposit_attested(
+        customer,
+        &make_id(env, seed),
+        merchant,
+        &1_000i128,
+        &DEFAULT_PAYMENT_TTL,
+        &AssetType::Usdc,
+        &BytesN::from_array(env, &attester.verifying_key().to_bytes()),
+        condition_hash,
+    )
+}
+
+fn sign_attestation(env: &Env, attester: &SigningKey, payload: &AttestationPayload) -> BytesN<64> {
+    let message = payload.clone().to_xdr(env);
+    let mut buf = [0u8; 512];
+    let buf = &mut buf[..message.len() as usize];
+    message.copy_into_slice(buf);
+    BytesN::from_array(env, &attester.sign(buf).to_bytes())
+}
+
+#[test]
+fn test_attestation_releases_to_merchant() {
+    let (env, client, contract_id, _admin, customer, merchant, usdc, ..) = setup_env();
+    let attester = SigningKey::from_bytes(&[1u8; 32]);
+    let condition_hash = make_id(&env, 80);
+    let payment_id =
+        deposit_attested(&env, &client, &customer, &merchant, 81, &attester, &condition_hash);
+
+    let payload = client.get_attestation_payload(&payment_id);
+    assert_eq!(payload.contract, contract_id);
+    assert_eq!(payload.payment_id, payment_id);
+    assert_eq!(payload.condition_hash, condition_hash);
+    let signature = sign_attestation(&env, &attester, &payload);
+
+    env.set_auths(&[]);
+    client.release_attested(&payment_id, &condition_hash, &signature);
+
+    let events = env.events().all();
+    let (_, topics, _) = events.get(events.len() - 1).unwrap();
+    assert_eq!(
+        String::try_from_val(&env, &topics.get(1).unwrap()).unwrap(),
+        String::from_str(&env, "attested")
+    );
+    assert_eq!(token::Client::new(&env, &usdc).balance(&merchant), 1_000);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Released);
+}
+
+#[test]
+fn test_attestation_must_match_condition_and_escrow() {
+    let (env, client, _contract_id, _admin, customer, merchant, ..) = setup_env();
+    let attester = SigningKey::from_bytes(&[2u8; 32]);
+    let condition_hash = make_id(&env, 82);
+    let first =
+        deposit_attested(&env, &client, &customer, &merchant, 83, &attester, &condition_hash);
+    let second =
+        deposit_attested(&env, &client, &customer, &merchant, 84, &attester, &condition_hash);
+    let signature = sign_attestation(&env, &attester, &client.get_attestation_payload(&first));
+
+    assert_eq!(
+        client.try_release_attested(&first, &make_id(&env, 85), &signature),
+        Err(Ok(EscrowError::ConditionMismatch))
+    );
+    // A signature over one escrow's payload does not verify for another.
+    assert!(client.try_release_attested(&second, &condition_hash, &signature).is_err());
+    let forged = sign_attestation(
+        &env,
+        &SigningKey::from_bytes(&[3u8; 32]),
+        &client.get_attestation_payload(&first),
+    );
+    assert!(client.try_release_attested(&first, &condition_hash, &forged).is_err());
+
+    client.release_attested(&first, &condition_hash, &signature);
+    assert_eq!(
+        client.try_release_attested(&first, &condition_hash, &signature),
+        Err(Ok(EscrowError::PaymentFullyReleased))
+    );
+    assert_eq!(client.get_payment(&second).status, PaymentStatus::Pending);
+
+    let plain = make_id(&env, 86);
+    deposit_default_ttl(&client, &customer, &plain, &merchant, 1_000i128);
+    assert_eq!(
+        client.try_release_attested(&plain, &condition_hash, &signature),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
+    );
+    assert_eq!(
+        client.try_get_attestation_payload(&plain),
+        Err(Ok(EscrowError::UnsupportedEscrowKind))
     );
 }
+
+
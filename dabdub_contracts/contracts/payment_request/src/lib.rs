@@ -1,8 +1,6 @@
 #![no_std]
 
-use soroban_sdk::{
-    contract, contractimpl, contracttype, symbol_short, Address, Env, String, Symbol,
-};
+use soroban_sdk::{contract, contractimpl, contracttype, symbol_short, Address, Env, String};
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -30,6 +28,9 @@ pub struct Payment {
 #[contracttype]
 pub enum DataKey {
     Payment(String),
+    Admin,
+    /// Contracts (e.g. PaymentEscrow) allowed to confirm payments
+    Confirmer(Address),
 }
 
 #[contract]
@@ -37,6 +38,10 @@ pub struct PaymentRequestContract;
 
 #[contractimpl]
 impl PaymentRequestContract {
+    pub fn __constructor(env: Env, admin: Address) {
+        env.storage().instance().set(&DataKey::Admin, &admin);
+    }
+
     /// Initialize a new payment request.
     pub fn create_payment(
         env: Env,
@@ -70,8 +75,12 @@ impl PaymentRequestContract {
         );
     }
 
-    /// Mark payment as confirmed (user has paid).
-    pub fn confirm(env: Env, id: String) {
+    /// Mark payment as confirmed (user has paid). Admin or an authorized
+    /// confirmer (the backend, or a contract such as PaymentEscrow) only.
+    pub fn confirm(env: Env, caller: Address, id: String) {
+        caller.require_auth();
+        Self::require_confirmer(&env, &caller);
+
         let key = DataKey::Payment(id.clone());
         let mut payment: Payment = env.storage().persistent().get(&key).expect("Payment not found");
 
@@ -173,6 +182,35 @@ impl PaymentRequestContract {
     pub fn get_payment(env: Env, id: String) -> Payment {
         env.storage().persistent().get(&DataKey::Payment(id)).expect("Payment not found")
     }
+
+    /// Grant or revoke a confirmer's right to confirm payments. Admin-only.
+    pub fn set_confirmer(env: Env, caller: Address, confirmer: Address, allowed: bool) {
+        caller.require_auth();
+        Self::require_admin(&env, &caller);
+        let key = DataKey::Confirmer(confirmer);
+        if allowed {
+            env.storage().instance().set(&key, &true);
+        } else {
+            env.storage().instance().remove(&key);
+        }
+    }
+
+    pub fn is_confirmer(env: Env, confirmer: Address) -> bool {
+        env.storage().instance().has(&DataKey::Confirmer(confirmer))
+    }
+
+    fn require_confirmer(env: &Env, caller: &Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        assert!(
+            caller == &admin || env.storage().instance().has(&DataKey::Confirmer(caller.clone())),
+            "not authorized confirmer"
+        );
+    }
+
+    fn require_admin(env: &Env, caller: &Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        assert!(caller == &admin, "not admin");
+    }
 }
 
 mod test;
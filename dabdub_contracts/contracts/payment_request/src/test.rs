@@ -8,7 +8,8 @@ fn test_payment_lifecycle_success() {
     let env = Env::default();
     env.mock_all_auths();
 
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -27,7 +28,7 @@ fn test_payment_lifecycle_success() {
     assert_eq!(payment.amount, amount);
 
     // 2. Confirm Payment
-    client.confirm(&payment_id);
+    client.confirm(&admin, &payment_id);
     assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Confirmed);
 
     // 3. Set Settling
@@ -43,7 +44,8 @@ fn test_payment_lifecycle_success() {
 fn test_direct_settlement_from_confirmed() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -51,7 +53,7 @@ fn test_direct_settlement_from_confirmed() {
     let payment_id = String::from_str(&env, "pay_123");
     
     client.create_payment(&payment_id, &merchant, &100, &asset, &1000);
-    client.confirm(&payment_id);
+    client.confirm(&admin, &payment_id);
     
     // Skip settling state
     client.settle(&payment_id);
@@ -63,7 +65,8 @@ fn test_direct_settlement_from_confirmed() {
 fn test_invalid_settle_from_pending() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -80,7 +83,8 @@ fn test_invalid_settle_from_pending() {
 fn test_payment_expiry() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -103,7 +107,8 @@ fn test_payment_expiry() {
 fn test_early_expiry_fails() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -123,7 +128,8 @@ fn test_early_expiry_fails() {
 fn test_payment_failure() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -131,7 +137,7 @@ fn test_payment_failure() {
     let payment_id = String::from_str(&env, "pay_123");
 
     client.create_payment(&payment_id, &merchant, &100, &asset, &1000);
-    client.confirm(&payment_id);
+    client.confirm(&admin, &payment_id);
     
     client.fail(&payment_id);
     assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Failed);
@@ -142,7 +148,8 @@ fn test_payment_failure() {
 fn test_fail_after_settled_panics() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PaymentRequestContract);
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
     let client = PaymentRequestContractClient::new(&env, &contract_id);
 
     let merchant = Address::generate(&env);
@@ -150,8 +157,47 @@ fn test_fail_after_settled_panics() {
     let payment_id = String::from_str(&env, "pay_123");
 
     client.create_payment(&payment_id, &merchant, &100, &asset, &1000);
-    client.confirm(&payment_id);
+    client.confirm(&admin, &payment_id);
     client.settle(&payment_id);
     
     client.fail(&payment_id);
 }
+
+#[test]
+fn test_authorized_confirmer_can_confirm() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
+    let client = PaymentRequestContractClient::new(&env, &contract_id);
+
+    let merchant = Address::generate(&env);
+    let asset = Address::generate(&env);
+    let confirmer = Address::generate(&env);
+    let payment_id = String::from_str(&env, "pay_123");
+
+    client.create_payment(&payment_id, &merchant, &100, &asset, &1000);
+    assert!(client.try_confirm(&confirmer, &payment_id).is_err());
+    assert!(client.try_confirm(&merchant, &payment_id).is_err());
+
+    client.set_confirmer(&admin, &confirmer, &true);
+    assert!(client.is_confirmer(&confirmer));
+    client.confirm(&confirmer, &payment_id);
+    assert_eq!(client.get_payment(&payment_id).status, PaymentStatus::Confirmed);
+
+    client.set_confirmer(&admin, &confirmer, &false);
+    assert!(!client.is_confirmer(&confirmer));
+}
+
+#[test]
+#[should_panic(expected = "not admin")]
+fn test_set_confirmer_admin_only() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let contract_id = env.register(PaymentRequestContract, (&admin,));
+    let client = PaymentRequestContractClient::new(&env, &contract_id);
+
+    let other = Address::generate(&env);
+    client.set_confirmer(&other, &other, &true);
+}
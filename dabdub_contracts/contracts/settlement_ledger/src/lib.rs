@@ -1,4 +1,5 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 
 mod test;
 
@@ -26,8 +27,13 @@ enum DataKey {
     Admin,
     /// SettlementRecord keyed by payment_id
     Settlement(BytesN<32>),
-    /// Vec<BytesN<32>> — ordered list of payment_ids per merchant
-    MerchantIndex(Address),
+    /// u32 — number of settlements recorded for a merchant
+    MerchantCount(Address),
+    /// Vec<BytesN<32>> — one PAGE_SIZE bucket of a merchant's payment_ids, in
+    /// order, so no single entry grows with the merchant's history
+    MerchantPage(Address, u32),
+    /// Contracts (e.g. PaymentEscrow) allowed to record settlements
+    Writer(Address),
 }
 
 // ── events ───────────────────────────────────────────────────────────────────
@@ -53,7 +59,8 @@ impl SettlementLedgerContract {
         env.storage().instance().set(&DataKey::Admin, &admin);
     }
 
-    /// Write an immutable settlement record. Admin-only (called by NestJS backend).
+    /// Write an immutable settlement record. Admin or an authorized writer
+    /// (the NestJS backend, or a contract such as PaymentEscrow).
     /// Panics if a record for `payment_id` already exists — records are append-only.
     pub fn record_settlement(
         env: Env,
@@ -67,7 +74,7 @@ impl SettlementLedgerContract {
         fiat_ref: String,
     ) {
         caller.require_auth();
-        Self::require_admin(&env, &caller);
+        Self::require_writer(&env, &caller);
 
         assert!(amount > 0, "amount must be > 0");
         assert!(fee >= 0, "fee must be >= 0");
@@ -89,15 +96,19 @@ impl SettlementLedgerContract {
 
         env.storage().persistent().set(&key, &record);
 
-        // Append payment_id to the merchant's index list
-        let idx_key = DataKey::MerchantIndex(merchant.clone());
-        let mut index: Vec<BytesN<32>> = env
+        // Append payment_id to the merchant's current index page
+        let count = Self::settlement_count(env.clone(), merchant.clone());
+        let page_key = DataKey::MerchantPage(merchant.clone(), count / PAGE_SIZE);
+        let mut page: Vec<BytesN<32>> = env
             .storage()
             .persistent()
-            .get(&idx_key)
+            .get(&page_key)
             .unwrap_or_else(|| vec![&env]);
-        index.push_back(payment_id.clone());
-        env.storage().persistent().set(&idx_key, &index);
+        page.push_back(payment_id.clone());
+        env.storage().persistent().set(&page_key, &page);
+        env.storage()
+            .persistent()
+            .set(&DataKey::MerchantCount(merchant.clone()), &(count + 1));
 
         env.events().publish(
             ("SETTLEMENT_LEDGER", "settlement_recorded"),
@@ -123,23 +134,14 @@ impl SettlementLedgerContract {
     /// Paginated list of settlement records for a merchant.
     /// `page` is 0-indexed; returns up to PAGE_SIZE (20) records per page.
     pub fn list_settlements(env: Env, merchant: Address, page: u32) -> Vec<SettlementRecord> {
-        let idx_key = DataKey::MerchantIndex(merchant);
         let index: Vec<BytesN<32>> = env
             .storage()
             .persistent()
-            .get(&idx_key)
+            .get(&DataKey::MerchantPage(merchant, page))
             .unwrap_or_else(|| vec![&env]);
 
-        let total = index.len();
-        let start = page.saturating_mul(PAGE_SIZE);
-        if start >= total {
-            return vec![&env];
-        }
-
-        let end = (start + PAGE_SIZE).min(total);
         let mut results: Vec<SettlementRecord> = vec![&env];
-        for i in start..end {
-            let pid = index.get(i).unwrap();
+        for pid in index.iter() {
             let record: SettlementRecord = env
                 .storage()
                 .persistent()
@@ -152,14 +154,36 @@ impl SettlementLedgerContract {
 
     /// Total number of settlements recorded for a merchant.
     pub fn settlement_count(env: Env, merchant: Address) -> u32 {
-        let idx_key = DataKey::MerchantIndex(merchant);
         env.storage()
             .persistent()
-            .get::<_, Vec<BytesN<32>>>(&idx_key)
-            .map(|v| v.len())
+            .get(&DataKey::MerchantCount(merchant))
             .unwrap_or(0)
     }
 
+    /// Grant or revoke a writer's right to record settlements. Admin-only.
+    pub fn set_writer(env: Env, caller: Address, writer: Address, allowed: bool) {
+        caller.require_auth();
+        Self::require_admin(&env, &caller);
+        let key = DataKey::Writer(writer);
+        if allowed {
+            env.storage().instance().set(&key, &true);
+        } else {
+            env.storage().instance().remove(&key);
+        }
+    }
+
+    pub fn is_writer(env: Env, writer: Address) -> bool {
+        env.storage().instance().has(&DataKey::Writer(writer))
+    }
+
+    fn require_writer(env: &Env, caller: &Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        assert!(
+            caller == &admin || env.storage().instance().has(&DataKey::Writer(caller.clone())),
+            "not authorized writer"
+        );
+    }
+
     fn require_admin(env: &Env, caller: &Address) {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
         assert!(caller == &admin, "not admin");
@@ -155,6 +155,29 @@ fn test_list_settlements_pagination() {
     assert_eq!(page2.len(), 0); // beyond end
 }
 
+#[test]
+fn test_merchant_index_write_does_not_grow_with_history() {
+    let (env, admin, client) = setup();
+    let merchant = Address::generate(&env);
+    record(&client, &admin, &env, 100, &merchant, 1_000, 20);
+
+    env.cost_estimate().budget().reset_default();
+    record(&client, &admin, &env, 101, &merchant, 1_000, 20);
+    let early = env.cost_estimate().resources().write_bytes;
+
+    for seed in 102u8..141 {
+        record(&client, &admin, &env, seed, &merchant, 1_000, 20);
+    }
+    env.cost_estimate().budget().reset_default();
+    record(&client, &admin, &env, 141, &merchant, 1_000, 20);
+    assert!(env.cost_estimate().resources().write_bytes <= early);
+
+    assert_eq!(client.settlement_count(&merchant), 42);
+    let page2 = client.list_settlements(&merchant, &2);
+    assert_eq!(page2.len(), 2);
+    assert_eq!(page2.get(0).unwrap().payment_id, pid(&env, 140));
+}
+
 // ── different merchants are isolated ─────────────────────────────────────────
 
 #[test]
@@ -179,3 +202,41 @@ fn test_get_unknown_panics() {
     let result = client.try_get_settlement(&pid(&env, 99));
     assert!(result.is_err());
 }
+
+// ── writers ───────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_authorized_writer_can_record() {
+    let (env, admin, client) = setup();
+    let writer = Address::generate(&env);
+    let merchant = Address::generate(&env);
+
+    assert!(client
+        .try_record_settlement(
+            &writer,
+            &pid(&env, 60),
+            &merchant,
+            &1_000,
+            &0,
+            &1_000,
+            &1_700_000_000u64,
+            &fiat_ref(&env, ""),
+        )
+        .is_err());
+
+    client.set_writer(&admin, &writer, &true);
+    assert!(client.is_writer(&writer));
+    record(&client, &writer, &env, 60, &merchant, 1_000, 10);
+    assert_eq!(client.get_settlement(&pid(&env, 60)).net, 990);
+
+    client.set_writer(&admin, &writer, &false);
+    assert!(!client.is_writer(&writer));
+}
+
+#[test]
+fn test_set_writer_admin_only() {
+    let (env, _admin, client) = setup();
+    let other = Address::generate(&env);
+    let result = client.try_set_writer(&other, &other, &true);
+    assert!(result.is_err());
+}